@@ -1,8 +1,22 @@
 import os
 import shutil
 import site
+import sys
 from pathlib import Path
 
+# onnxruntime's own library and its provider plugins
+if sys.platform == "win32":
+    LIB_SUFFIXES = (".dll",)
+elif sys.platform == "darwin":
+    LIB_SUFFIXES = (".dylib",)
+else:
+    LIB_SUFFIXES = (".so",)
+
+
+def is_runtime_lib(fname):
+    # Linux wheels ship versioned names like libonnxruntime.so.1.20.0
+    return fname.endswith(LIB_SUFFIXES) or ".so." in fname
+
 
 def copy_ryzenai_onnx_runtime(targets):
     installation_path = os.environ["RYZEN_AI_INSTALLATION_PATH"]
@@ -29,11 +43,17 @@ def copy_python_runtime(targets):
         if onnx_lib_path.exists():
             for target in targets:
                 dest_dir = f"./target/{target}"
+                os.makedirs(dest_dir, exist_ok=True)
                 for fname in os.listdir(onnx_lib_path):
-                    if fname.endswith(".dll"):
+                    if is_runtime_lib(fname):
                         fpath = Path(onnx_lib_path, fname)
                         shutil.copy2(fpath, dest_dir)
                         print("Copy:", fpath, "->", dest_dir)
+                        if fname.startswith("libonnxruntime.so."):
+                            # ort loads the unversioned name next to the executable
+                            link = Path(dest_dir, "libonnxruntime.so")
+                            shutil.copy2(fpath, link)
+                            print("Copy:", fpath, "->", link)
             return True
 
 
@@ -0,0 +1,521 @@
+//! Command line options and the optional TOML config file.
+//!
+//! Every setting has a built-in default, can be set in the file given with `--config` and can
+//! be overridden on the command line:
+//!
+//! ```toml
+//! title = "Face landmarks"
+//! headless = false
+//! staged = false          # overlap detection and landmarks, more fps for one frame more latency
+//! outputs = ["results.jsonl"]
+//!
+//! [source]
+//! input = "clip.nv12"   # omit to use the default camera
+//! raw = "nv12"          # read `input` as raw frames, needs `size`
+//! size = [1280, 720]
+//! fps = 30.0           # raw input and images, y4m files carry their own
+//!
+//! [models]
+//! detector = "weights/yolov5n-face-relu.onnx"
+//! landmarker = "weights/face_landmarks_detector.onnx"
+//!
+//! [detection]
+//! preprocess = "letterbox" # letterbox or crop, crop only searches the centred square
+//! tile_size = 1280        # also detect on overlapping tiles of this size, 0 disables tiling
+//! tile_overlap = 160
+//! conf = 0.6
+//! iou = 0.5
+//! nms = "standard"        # standard, soft-linear, soft-gaussian or diou
+//! nms_sigma = 0.5         # soft-gaussian decay
+//! crop_margin = 1.5
+//! align_crops = true      # roll crops to level the eyes
+//! max_faces = 3
+//! track = true            # follow faces with their landmarks between detector runs
+//! redetect_interval = 10
+//! track_score = 0.7
+//! max_lost = 30           # frames a face may be missing before it gets a new id
+//!
+//! [smoothing]
+//! method = "one-euro"     # one-euro, ema or none
+//! min_cutoff = 1.0
+//! beta = 0.01
+//! d_cutoff = 1.0
+//! alpha = 0.5             # ema weight of the newest frame
+//!
+//! [session]
+//! providers = ["cuda", "cpu"]
+//! intra_threads = 4
+//! inter_threads = 4
+//! opt_level = "level3"
+//! parallel_execution = true
+//! ```
+
+use std::path::PathBuf;
+
+use anyhow::{Context, anyhow, bail};
+use clap::Parser;
+use serde::Deserialize;
+
+use crate::model::SessionOptions;
+use crate::smoothing::Smoothing;
+use crate::source::{self, FrameSource, VideoSource};
+use crate::yolov5_face::{Nms, Preprocess, Tiling};
+
+#[derive(Debug, Parser)]
+#[command(
+    version,
+    about = "Face detection and 478 point face landmarks on ONNX Runtime"
+)]
+pub struct Cli {
+    /// Image, glob, directory, .y4m or raw video to read instead of the default camera
+    pub input: Option<String>,
+
+    /// TOML file providing defaults for the options below
+    #[arg(short, long)]
+    pub config: Option<PathBuf>,
+
+    /// Face detector model [default: weights/yolov5n-face-relu.onnx]
+    #[arg(long)]
+    pub detector: Option<PathBuf>,
+    /// Face landmark model [default: weights/face_landmarks_detector.onnx]
+    #[arg(long)]
+    pub landmarker: Option<PathBuf>,
+
+    /// Fit frames into the detector by padding (letterbox) or by cropping the centre (crop)
+    /// [default: letterbox]
+    #[arg(long)]
+    pub preprocess: Option<String>,
+    /// Also detect on overlapping tiles of this many pixels, finds small faces in high
+    /// resolution frames at the cost of one detector run per tile [default: off]
+    #[arg(long)]
+    pub tile_size: Option<u32>,
+    /// Pixels shared by neighbouring tiles [default: 160]
+    #[arg(long)]
+    pub tile_overlap: Option<u32>,
+    /// Minimum face detection confidence [default: 0.6]
+    #[arg(long)]
+    pub conf: Option<f32>,
+    /// IoU above which overlapping detections are merged [default: 0.5]
+    #[arg(long)]
+    pub iou: Option<f32>,
+    /// Suppression of overlapping detections: standard, soft-linear, soft-gaussian or diou
+    /// [default: standard]
+    #[arg(long)]
+    pub nms: Option<String>,
+    /// Landmark crop size relative to the detected box [default: 1.5]
+    #[arg(long)]
+    pub crop_margin: Option<f32>,
+    /// Use axis aligned landmark crops instead of rolling them to level the eyes
+    #[arg(long)]
+    pub no_align: bool,
+    /// Maximum number of faces to compute landmarks for [default: 3]
+    #[arg(long)]
+    pub max_faces: Option<usize>,
+    /// Follow faces with their landmarks and only run the detector periodically
+    #[arg(long)]
+    pub track: bool,
+    /// Frames between detector runs while tracking [default: 10]
+    #[arg(long)]
+    pub redetect_interval: Option<u32>,
+    /// Landmark score below which a tracked face is detected again [default: 0.7]
+    #[arg(long)]
+    pub track_score: Option<f32>,
+    /// Frames a face may go unseen before its id is dropped [default: 30]
+    #[arg(long)]
+    pub max_lost: Option<u32>,
+
+    /// Landmark smoothing: one-euro, ema or none [default: one-euro]
+    #[arg(long)]
+    pub smoothing: Option<String>,
+    /// One Euro cutoff frequency at rest in Hz, lower is smoother [default: 1.0]
+    #[arg(long)]
+    pub min_cutoff: Option<f32>,
+    /// One Euro speed coefficient, higher lags less [default: 0.01]
+    #[arg(long)]
+    pub beta: Option<f32>,
+    /// EMA weight of the newest frame [default: 0.5]
+    #[arg(long)]
+    pub ema_alpha: Option<f32>,
+
+    /// Read INPUT as raw frames in this pixel format (bgra, nv12), requires --size
+    #[arg(long)]
+    pub raw: Option<String>,
+    /// Frame size of raw input as WIDTHxHEIGHT
+    #[arg(long, value_parser = parse_size)]
+    pub size: Option<(u32, u32)>,
+    /// Frame rate of raw input and images [default: 30], y4m files carry their own
+    #[arg(long)]
+    pub fps: Option<f32>,
+
+    /// Write results to a sink, may be repeated: `-` writes JSON Lines to stdout, `*.lmrec` a
+    /// binary recording and anything else a JSON Lines file
+    #[arg(short, long)]
+    pub output: Vec<String>,
+    /// Run the pipeline without opening a window
+    #[arg(long)]
+    pub headless: bool,
+    /// Run detection and landmarks on separate threads, so the next frame is detected while
+    /// the landmarks of the previous one are computed. Raises the frame rate up to that of
+    /// the slower stage, but results arrive up to one frame later. Applies to the window, so
+    /// it can't be combined with --headless or --track
+    #[arg(long)]
+    pub staged: bool,
+    /// Draw the results of a recording instead of running inference
+    #[arg(long)]
+    pub replay: Option<PathBuf>,
+    /// Window title
+    #[arg(long)]
+    pub title: Option<String>,
+
+    /// Comma separated execution providers in priority order, e.g. `cuda,cpu`
+    #[arg(long)]
+    pub providers: Option<String>,
+    /// Threads used within an operator
+    #[arg(long)]
+    pub intra_threads: Option<usize>,
+    /// Threads used across operators [default: 4]
+    #[arg(long)]
+    pub inter_threads: Option<usize>,
+    /// Graph optimization level: disable, level1, level2 or level3 [default: level3]
+    #[arg(long)]
+    pub opt_level: Option<String>,
+    /// Execute the graph sequentially instead of in parallel
+    #[arg(long)]
+    pub sequential: bool,
+}
+
+fn parse_size(s: &str) -> anyhow::Result<(u32, u32)> {
+    let (w, h) = s
+        .split_once(['x', 'X'])
+        .ok_or_else(|| anyhow!("expected WIDTHxHEIGHT, found `{}`", s))?;
+    Ok((w.trim().parse()?, h.trim().parse()?))
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub title: String,
+    pub headless: bool,
+    /// Pipeline detection and landmarks, see [`Cli::staged`].
+    pub staged: bool,
+    pub outputs: Vec<String>,
+    pub replay: Option<PathBuf>,
+    pub source: SourceConfig,
+    pub models: ModelsConfig,
+    pub detection: DetectionConfig,
+    pub smoothing: SmoothingConfig,
+    pub session: SessionConfig,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SourceConfig {
+    /// `None` selects the default camera.
+    pub input: Option<String>,
+    pub raw: Option<String>,
+    pub size: Option<(u32, u32)>,
+    pub fps: Option<f32>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ModelsConfig {
+    pub detector: PathBuf,
+    pub landmarker: PathBuf,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DetectionConfig {
+    pub preprocess: String,
+    pub tile_size: u32,
+    pub tile_overlap: u32,
+    pub conf: f32,
+    pub iou: f32,
+    pub nms: String,
+    pub nms_sigma: f32,
+    pub crop_margin: f32,
+    pub align_crops: bool,
+    pub max_faces: usize,
+    pub track: bool,
+    pub redetect_interval: u32,
+    pub track_score: f32,
+    pub max_lost: u32,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SmoothingConfig {
+    pub method: String,
+    pub min_cutoff: f32,
+    pub beta: f32,
+    pub d_cutoff: f32,
+    pub alpha: f32,
+}
+
+/// Overrides of [`SessionOptions`], unset fields keep its defaults.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    pub providers: Option<Vec<String>>,
+    pub intra_threads: Option<usize>,
+    pub inter_threads: Option<usize>,
+    pub opt_level: Option<String>,
+    pub parallel_execution: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            title: "Yolov5Face-FacelandMark(MobileNet)@Google".into(),
+            headless: false,
+            staged: false,
+            outputs: Vec::new(),
+            replay: None,
+            source: SourceConfig::default(),
+            models: ModelsConfig::default(),
+            detection: DetectionConfig::default(),
+            smoothing: SmoothingConfig::default(),
+            session: SessionConfig::default(),
+        }
+    }
+}
+
+impl Default for ModelsConfig {
+    fn default() -> Self {
+        Self {
+            detector: "weights/yolov5n-face-relu.onnx".into(),
+            landmarker: "weights/face_landmarks_detector.onnx".into(),
+        }
+    }
+}
+
+impl Default for DetectionConfig {
+    fn default() -> Self {
+        Self {
+            preprocess: "letterbox".into(),
+            tile_size: 0,
+            tile_overlap: 160,
+            conf: 0.6,
+            iou: 0.5,
+            nms: "standard".into(),
+            nms_sigma: Nms::DEFAULT_SIGMA,
+            crop_margin: 1.5,
+            align_crops: true,
+            max_faces: 3,
+            track: false,
+            redetect_interval: 10,
+            track_score: 0.7,
+            max_lost: 30,
+        }
+    }
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> Self {
+        Self {
+            method: "one-euro".into(),
+            min_cutoff: 1.0,
+            beta: 0.01,
+            d_cutoff: 1.0,
+            alpha: 0.5,
+        }
+    }
+}
+
+impl Config {
+    /// Parses the command line, loading the config file it names if any.
+    pub fn load() -> anyhow::Result<Self> {
+        let cli = Cli::parse();
+        let mut config = match &cli.config {
+            Some(path) => {
+                let text = std::fs::read_to_string(path)
+                    .with_context(|| format!("failed to read {}", path.display()))?;
+                toml::from_str(&text)
+                    .with_context(|| format!("failed to parse {}", path.display()))?
+            }
+            None => Config::default(),
+        };
+        config.apply(cli);
+        Ok(config)
+    }
+
+    fn apply(&mut self, cli: Cli) {
+        if let Some(title) = cli.title {
+            self.title = title;
+        }
+        self.headless |= cli.headless;
+        self.staged |= cli.staged;
+        self.outputs.extend(cli.output);
+        if cli.replay.is_some() {
+            self.replay = cli.replay;
+        }
+
+        let source = &mut self.source;
+        if cli.input.is_some() {
+            source.input = cli.input;
+        }
+        if cli.raw.is_some() {
+            source.raw = cli.raw;
+        }
+        if cli.size.is_some() {
+            source.size = cli.size;
+        }
+        if cli.fps.is_some() {
+            source.fps = cli.fps;
+        }
+
+        if let Some(detector) = cli.detector {
+            self.models.detector = detector;
+        }
+        if let Some(landmarker) = cli.landmarker {
+            self.models.landmarker = landmarker;
+        }
+
+        let detection = &mut self.detection;
+        if let Some(preprocess) = cli.preprocess {
+            detection.preprocess = preprocess;
+        }
+        detection.tile_size = cli.tile_size.unwrap_or(detection.tile_size);
+        detection.tile_overlap = cli.tile_overlap.unwrap_or(detection.tile_overlap);
+        detection.conf = cli.conf.unwrap_or(detection.conf);
+        detection.iou = cli.iou.unwrap_or(detection.iou);
+        if let Some(nms) = cli.nms {
+            detection.nms = nms;
+        }
+        detection.crop_margin = cli.crop_margin.unwrap_or(detection.crop_margin);
+        detection.align_crops &= !cli.no_align;
+        detection.max_faces = cli.max_faces.unwrap_or(detection.max_faces);
+        detection.track |= cli.track;
+        detection.redetect_interval = cli.redetect_interval.unwrap_or(detection.redetect_interval);
+        detection.track_score = cli.track_score.unwrap_or(detection.track_score);
+        detection.max_lost = cli.max_lost.unwrap_or(detection.max_lost);
+
+        let smoothing = &mut self.smoothing;
+        if let Some(method) = cli.smoothing {
+            smoothing.method = method;
+        }
+        smoothing.min_cutoff = cli.min_cutoff.unwrap_or(smoothing.min_cutoff);
+        smoothing.beta = cli.beta.unwrap_or(smoothing.beta);
+        smoothing.alpha = cli.ema_alpha.unwrap_or(smoothing.alpha);
+
+        let session = &mut self.session;
+        if let Some(providers) = cli.providers {
+            session.providers = Some(providers.split(',').map(str::to_string).collect());
+        }
+        if cli.intra_threads.is_some() {
+            session.intra_threads = cli.intra_threads;
+        }
+        if cli.inter_threads.is_some() {
+            session.inter_threads = cli.inter_threads;
+        }
+        if cli.opt_level.is_some() {
+            session.opt_level = cli.opt_level;
+        }
+        if cli.sequential {
+            session.parallel_execution = Some(false);
+        }
+    }
+
+    /// Whether to run the pipeline in stages, rejecting combinations that need it serial.
+    pub fn staged(&self) -> anyhow::Result<bool> {
+        if self.staged && self.headless {
+            bail!("--staged can't be combined with --headless, which runs the pipeline serially");
+        }
+        if self.staged && self.detection.track {
+            bail!(
+                "--staged can't be combined with --track, which needs each frame's landmarks before detecting the next"
+            );
+        }
+        Ok(self.staged)
+    }
+
+    pub fn session_options(&self) -> anyhow::Result<SessionOptions> {
+        let session = &self.session;
+        let mut options = SessionOptions::default();
+        if let Some(providers) = &session.providers {
+            options.providers = SessionOptions::parse_providers(&providers.join(","))?;
+        }
+        if session.intra_threads.is_some() {
+            options.intra_threads = session.intra_threads;
+        }
+        if session.inter_threads.is_some() {
+            options.inter_threads = session.inter_threads;
+        }
+        if let Some(level) = &session.opt_level {
+            options.optimization_level = level.parse()?;
+        }
+        if let Some(parallel) = session.parallel_execution {
+            options.parallel_execution = parallel;
+        }
+        Ok(options)
+    }
+}
+
+impl DetectionConfig {
+    pub fn preprocess(&self) -> anyhow::Result<Preprocess> {
+        self.preprocess.parse()
+    }
+
+    pub fn tiling(&self) -> anyhow::Result<Option<Tiling>> {
+        if self.tile_size == 0 {
+            return Ok(None);
+        }
+        if self.tile_overlap >= self.tile_size {
+            anyhow::bail!(
+                "tile overlap {} must be smaller than the tile size {}",
+                self.tile_overlap,
+                self.tile_size
+            );
+        }
+        Ok(Some(Tiling {
+            size: self.tile_size,
+            overlap: self.tile_overlap,
+        }))
+    }
+
+    pub fn nms(&self) -> anyhow::Result<Nms> {
+        Ok(match self.nms.parse()? {
+            Nms::SoftGaussian { .. } => Nms::SoftGaussian {
+                sigma: self.nms_sigma,
+            },
+            nms => nms,
+        })
+    }
+}
+
+impl SmoothingConfig {
+    pub fn smoothing(&self) -> anyhow::Result<Smoothing> {
+        Ok(match self.method.parse()? {
+            Smoothing::OneEuro { .. } => Smoothing::OneEuro {
+                min_cutoff: self.min_cutoff,
+                beta: self.beta,
+                d_cutoff: self.d_cutoff,
+            },
+            Smoothing::Ema { .. } => Smoothing::Ema { alpha: self.alpha },
+            Smoothing::None => Smoothing::None,
+        })
+    }
+}
+
+impl SourceConfig {
+    /// Opens the configured file source, `Ok(None)` when no input is set.
+    pub fn open(&self) -> anyhow::Result<Option<Box<dyn FrameSource + Send>>> {
+        let Some(input) = &self.input else {
+            return Ok(None);
+        };
+        let Some(format) = &self.raw else {
+            return Ok(Some(source::open(input, self.fps)?));
+        };
+        let Some(size) = self.size else {
+            bail!("raw input {} needs a frame size", input);
+        };
+        let fps = self.fps.unwrap_or(30.0);
+        Ok(Some(Box::new(VideoSource::open_raw(
+            input,
+            format.parse()?,
+            size,
+            fps,
+        )?)))
+    }
+}
@@ -0,0 +1,596 @@
+//! Conversion of BGRA images into normalised `f32` model input tensors.
+//!
+//! Every output value is `(pixel / 255 - mean) / std` for its channel, so with a mean of 0 and
+//! a std of 1 the result is bit for bit `pixel as f32 / 255.0`. The SSE2 and AVX2 paths compute
+//! exactly the same operations as the scalar reference and give identical results.
+
+use rayon::iter::{IndexedParallelIterator, ParallelIterator};
+use rayon::slice::{ParallelSlice, ParallelSliceMut};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    /// `[height, width, channels]`, channels interleaved.
+    Nhwc,
+    /// `[channels, height, width]`, one plane per channel.
+    Nchw,
+}
+
+/// Order of the channels in the output.
+#[allow(unused)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelOrder {
+    Rgb,
+    Bgr,
+}
+
+/// Source rows and the matching rows of the three output planes.
+type PlaneChunks<'a> = (((&'a [u8], &'a mut [f32]), &'a mut [f32]), &'a mut [f32]);
+
+#[derive(Debug, Clone, Copy)]
+pub struct Convert {
+    pub layout: Layout,
+    pub order: ChannelOrder,
+    /// Subtracted from the 0..1 value of each output channel.
+    pub mean: [f32; 3],
+    /// Divides each output channel after subtracting the mean.
+    pub std: [f32; 3],
+}
+
+impl Convert {
+    /// Rows converted by one rayon task, small inputs are done on the calling thread.
+    const ROWS_PER_TASK: usize = 64;
+
+    /// RGB scaled to 0..1.
+    pub const fn unit(layout: Layout) -> Self {
+        Self {
+            layout,
+            order: ChannelOrder::Rgb,
+            mean: [0.0; 3],
+            std: [1.0; 3],
+        }
+    }
+
+    /// Byte of a BGRA pixel feeding output channel `c`.
+    #[inline]
+    fn src_byte(&self, c: usize) -> usize {
+        match self.order {
+            ChannelOrder::Rgb => 2 - c,
+            ChannelOrder::Bgr => c,
+        }
+    }
+
+    #[inline]
+    fn value(&self, pixel: &[u8], c: usize) -> f32 {
+        (pixel[self.src_byte(c)] as f32 / 255.0 - self.mean[c]) / self.std[c]
+    }
+
+    /// Converts a tightly packed `width` x `height` BGRA image into `dst`, which holds
+    /// `width * height * 3` values in [`Convert::layout`].
+    pub fn run(&self, src: &[u8], width: usize, height: usize, dst: &mut [f32]) {
+        self.run_with(src, width, height, dst, true);
+    }
+
+    /// Plain Rust implementation the SIMD path is checked against.
+    #[cfg(test)]
+    pub fn run_scalar(&self, src: &[u8], width: usize, height: usize, dst: &mut [f32]) {
+        self.run_with(src, width, height, dst, false);
+    }
+
+    fn run_with(&self, src: &[u8], width: usize, height: usize, dst: &mut [f32], simd: bool) {
+        let pixels = width * height;
+        assert_eq!(src.len(), pixels * 4, "source is not a packed BGRA image");
+        assert_eq!(dst.len(), pixels * 3, "destination doesn't hold the image");
+        if pixels == 0 {
+            return;
+        }
+        let chunk = width * Self::ROWS_PER_TASK;
+        match self.layout {
+            Layout::Nhwc => {
+                let convert = |(src, dst): (&[u8], &mut [f32])| match simd {
+                    true => self.nhwc(src, dst),
+                    false => self.nhwc_scalar(src, dst),
+                };
+                if height <= Self::ROWS_PER_TASK {
+                    convert((src, dst));
+                } else {
+                    src.par_chunks(chunk * 4)
+                        .zip(dst.par_chunks_mut(chunk * 3))
+                        .for_each(convert);
+                }
+            }
+            Layout::Nchw => {
+                let (c0, rest) = dst.split_at_mut(pixels);
+                let (c1, c2) = rest.split_at_mut(pixels);
+                let convert = |(((src, c0), c1), c2): PlaneChunks| {
+                    let planes = [c0, c1, c2];
+                    match simd {
+                        true => self.nchw(src, planes),
+                        false => self.nchw_scalar(src, planes),
+                    }
+                };
+                if height <= Self::ROWS_PER_TASK {
+                    convert((((src, c0), c1), c2));
+                } else {
+                    src.par_chunks(chunk * 4)
+                        .zip(c0.par_chunks_mut(chunk))
+                        .zip(c1.par_chunks_mut(chunk))
+                        .zip(c2.par_chunks_mut(chunk))
+                        .for_each(convert);
+                }
+            }
+        }
+    }
+
+    fn nhwc_scalar(&self, src: &[u8], dst: &mut [f32]) {
+        for (pixel, out) in src.chunks_exact(4).zip(dst.chunks_exact_mut(3)) {
+            for (c, out) in out.iter_mut().enumerate() {
+                *out = self.value(pixel, c);
+            }
+        }
+    }
+
+    fn nchw_scalar(&self, src: &[u8], planes: [&mut [f32]; 3]) {
+        for (c, plane) in planes.into_iter().enumerate() {
+            for (pixel, out) in src.chunks_exact(4).zip(plane.iter_mut()) {
+                *out = self.value(pixel, c);
+            }
+        }
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn nhwc(&self, src: &[u8], dst: &mut [f32]) {
+        self.nhwc_scalar(src, dst);
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    fn nchw(&self, src: &[u8], planes: [&mut [f32]; 3]) {
+        self.nchw_scalar(src, planes);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn nhwc(&self, src: &[u8], dst: &mut [f32]) {
+        let avx2 = is_x86_feature_detected!("avx2");
+        let simd_pixels = src.len() / 4 / simd::width(avx2) * simd::width(avx2);
+        let (src, src_tail) = src.split_at(simd_pixels * 4);
+        let (dst, dst_tail) = dst.split_at_mut(simd_pixels * 3);
+        // SAFETY: AVX2 was detected, SSE2 is part of x86_64, the lengths are whole vectors
+        unsafe {
+            match avx2 {
+                true => simd::nhwc_avx2(self, src, dst),
+                false => simd::nhwc_sse2(self, src, dst),
+            }
+        }
+        self.nhwc_scalar(src_tail, dst_tail);
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    fn nchw(&self, src: &[u8], planes: [&mut [f32]; 3]) {
+        let avx2 = is_x86_feature_detected!("avx2");
+        let simd_pixels = src.len() / 4 / simd::width(avx2) * simd::width(avx2);
+        let (src, src_tail) = src.split_at(simd_pixels * 4);
+        let [(c0, c0_tail), (c1, c1_tail), (c2, c2_tail)] =
+            planes.map(|plane| plane.split_at_mut(simd_pixels));
+        // SAFETY: AVX2 was detected, SSE2 is part of x86_64, the lengths are whole vectors
+        unsafe {
+            match avx2 {
+                true => simd::nchw_avx2(self, src, [c0, c1, c2]),
+                false => simd::nchw_sse2(self, src, [c0, c1, c2]),
+            }
+        }
+        self.nchw_scalar(src_tail, [c0_tail, c1_tail, c2_tail]);
+    }
+}
+
+/// Each 32-bit lane holds one BGRA pixel, a channel is extracted by shifting and masking its
+/// byte. The AVX2 variants convert eight pixels at a time, the SSE2 ones four.
+#[cfg(target_arch = "x86_64")]
+mod simd {
+    use std::arch::x86_64::*;
+
+    use super::Convert;
+
+    /// Pixels per vector.
+    pub fn width(avx2: bool) -> usize {
+        match avx2 {
+            true => 8,
+            false => 4,
+        }
+    }
+
+    /// Per channel shift and normalisation. Subtracting a zero mean and dividing by a unit std
+    /// don't change any value, so they are skipped.
+    struct Params<'a> {
+        convert: &'a Convert,
+        shift: [__m128i; 3],
+        sub_mean: bool,
+        div_std: bool,
+    }
+
+    impl<'a> Params<'a> {
+        #[inline(always)]
+        unsafe fn new(convert: &'a Convert) -> Self {
+            unsafe {
+                Self {
+                    convert,
+                    shift: [0, 1, 2].map(|c| _mm_cvtsi32_si128(convert.src_byte(c) as i32 * 8)),
+                    sub_mean: convert.mean != [0.0; 3],
+                    div_std: convert.std != [1.0; 3],
+                }
+            }
+        }
+
+        /// Normalised output channels of four pixels.
+        #[inline(always)]
+        unsafe fn channels_sse2(&self, src: *const u8) -> [__m128; 3] {
+            unsafe {
+                let pixels = _mm_loadu_si128(src as *const __m128i);
+                [
+                    self.channel_sse2(pixels, 0),
+                    self.channel_sse2(pixels, 1),
+                    self.channel_sse2(pixels, 2),
+                ]
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn channel_sse2(&self, pixels: __m128i, c: usize) -> __m128 {
+            unsafe {
+                let byte =
+                    _mm_and_si128(_mm_srl_epi32(pixels, self.shift[c]), _mm_set1_epi32(0xff));
+                let mut value = _mm_div_ps(_mm_cvtepi32_ps(byte), _mm_set1_ps(255.0));
+                if self.sub_mean {
+                    value = _mm_sub_ps(value, _mm_set1_ps(self.convert.mean[c]));
+                }
+                if self.div_std {
+                    value = _mm_div_ps(value, _mm_set1_ps(self.convert.std[c]));
+                }
+                value
+            }
+        }
+
+        /// Normalised output channels of eight pixels. Closures don't inherit the AVX2 target
+        /// feature, so the channels are spelled out.
+        #[inline(always)]
+        unsafe fn channels_avx2(&self, src: *const u8) -> [__m256; 3] {
+            unsafe {
+                let pixels = _mm256_loadu_si256(src as *const __m256i);
+                [
+                    self.channel_avx2(pixels, 0),
+                    self.channel_avx2(pixels, 1),
+                    self.channel_avx2(pixels, 2),
+                ]
+            }
+        }
+
+        #[inline(always)]
+        unsafe fn channel_avx2(&self, pixels: __m256i, c: usize) -> __m256 {
+            unsafe {
+                let byte = _mm256_and_si256(
+                    _mm256_srl_epi32(pixels, self.shift[c]),
+                    _mm256_set1_epi32(0xff),
+                );
+                let mut value = _mm256_div_ps(_mm256_cvtepi32_ps(byte), _mm256_set1_ps(255.0));
+                if self.sub_mean {
+                    value = _mm256_sub_ps(value, _mm256_set1_ps(self.convert.mean[c]));
+                }
+                if self.div_std {
+                    value = _mm256_div_ps(value, _mm256_set1_ps(self.convert.std[c]));
+                }
+                value
+            }
+        }
+    }
+
+    /// Interleaves the channels of four pixels into the twelve values at `out`.
+    #[inline(always)]
+    unsafe fn store_nhwc(out: *mut f32, [c0, c1, c2]: [__m128; 3]) {
+        unsafe {
+            // transpose to one [c0, c1, c2, 0] vector per pixel
+            let zero = _mm_setzero_ps();
+            let lo01 = _mm_unpacklo_ps(c0, c1);
+            let hi01 = _mm_unpackhi_ps(c0, c1);
+            let lo2 = _mm_unpacklo_ps(c2, zero);
+            let hi2 = _mm_unpackhi_ps(c2, zero);
+            // each store spills one value into the next pixel, which overwrites it
+            _mm_storeu_ps(out, _mm_movelh_ps(lo01, lo2));
+            _mm_storeu_ps(out.add(3), _mm_movehl_ps(lo2, lo01));
+            _mm_storeu_ps(out.add(6), _mm_movelh_ps(hi01, hi2));
+            // the last pixel may end the slice
+            let mut last = [0.0f32; 4];
+            _mm_storeu_ps(last.as_mut_ptr(), _mm_movehl_ps(hi2, hi01));
+            std::ptr::copy_nonoverlapping(last.as_ptr(), out.add(9), 3);
+        }
+    }
+
+    /// `src` holds a multiple of four pixels, `dst` three values per pixel.
+    pub unsafe fn nhwc_sse2(convert: &Convert, src: &[u8], dst: &mut [f32]) {
+        debug_assert_eq!(src.len() % 16, 0);
+        debug_assert_eq!(src.len() / 4 * 3, dst.len());
+        unsafe {
+            let params = Params::new(convert);
+            for i in 0..src.len() / 16 {
+                let values = params.channels_sse2(src.as_ptr().add(i * 16));
+                store_nhwc(dst.as_mut_ptr().add(i * 12), values);
+            }
+        }
+    }
+
+    /// `src` holds a multiple of eight pixels, `dst` three values per pixel.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn nhwc_avx2(convert: &Convert, src: &[u8], dst: &mut [f32]) {
+        debug_assert_eq!(src.len() % 32, 0);
+        debug_assert_eq!(src.len() / 4 * 3, dst.len());
+        unsafe {
+            let params = Params::new(convert);
+            for i in 0..src.len() / 32 {
+                let [c0, c1, c2] = params.channels_avx2(src.as_ptr().add(i * 32));
+                let out = dst.as_mut_ptr().add(i * 24);
+                store_nhwc(
+                    out,
+                    [
+                        _mm256_castps256_ps128(c0),
+                        _mm256_castps256_ps128(c1),
+                        _mm256_castps256_ps128(c2),
+                    ],
+                );
+                store_nhwc(
+                    out.add(12),
+                    [
+                        _mm256_extractf128_ps::<1>(c0),
+                        _mm256_extractf128_ps::<1>(c1),
+                        _mm256_extractf128_ps::<1>(c2),
+                    ],
+                );
+            }
+        }
+    }
+
+    /// `src` holds a multiple of four pixels, every plane one value per pixel.
+    pub unsafe fn nchw_sse2(convert: &Convert, src: &[u8], planes: [&mut [f32]; 3]) {
+        debug_assert_eq!(src.len() % 16, 0);
+        debug_assert!(planes.iter().all(|p| p.len() == src.len() / 4));
+        unsafe {
+            let params = Params::new(convert);
+            let [c0, c1, c2] = planes;
+            for i in 0..src.len() / 16 {
+                let values = params.channels_sse2(src.as_ptr().add(i * 16));
+                _mm_storeu_ps(c0.as_mut_ptr().add(i * 4), values[0]);
+                _mm_storeu_ps(c1.as_mut_ptr().add(i * 4), values[1]);
+                _mm_storeu_ps(c2.as_mut_ptr().add(i * 4), values[2]);
+            }
+        }
+    }
+
+    /// `src` holds a multiple of eight pixels, every plane one value per pixel.
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn nchw_avx2(convert: &Convert, src: &[u8], planes: [&mut [f32]; 3]) {
+        debug_assert_eq!(src.len() % 32, 0);
+        debug_assert!(planes.iter().all(|p| p.len() == src.len() / 4));
+        unsafe {
+            let params = Params::new(convert);
+            let [c0, c1, c2] = planes;
+            for i in 0..src.len() / 32 {
+                let values = params.channels_avx2(src.as_ptr().add(i * 32));
+                _mm256_storeu_ps(c0.as_mut_ptr().add(i * 8), values[0]);
+                _mm256_storeu_ps(c1.as_mut_ptr().add(i * 8), values[1]);
+                _mm256_storeu_ps(c2.as_mut_ptr().add(i * 8), values[2]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use ndarray::{ArrayView, ArrayViewMut, Axis, s};
+    use rayon::iter::{ParallelBridge, ParallelIterator};
+
+    use super::*;
+
+    /// Deterministic image covering every byte value.
+    fn image(width: usize, height: usize) -> Vec<u8> {
+        (0..width * height * 4)
+            .map(|i| (i.wrapping_mul(2654435761) >> 7) as u8)
+            .collect()
+    }
+
+    /// The conversion the model wrappers did before this module, NHWC RGB.
+    fn previous_nhwc(src: &[u8], width: usize, height: usize, out: &mut [f32]) {
+        let view = ArrayView::from_shape((height, width, 4), src).unwrap();
+        let rgb = view.slice(s![.., .., 0..3;-1]);
+        let mut out = ArrayViewMut::from_shape((height, width, 3), out).unwrap();
+        rgb.axis_iter(Axis(0))
+            .zip(out.axis_iter_mut(Axis(0)))
+            .par_bridge()
+            .for_each(|(old, mut new)| {
+                new.zip_mut_with(&old, |new, old| *new = *old as f32 / 255.0);
+            });
+    }
+
+    /// The conversion the model wrappers did before this module, NCHW RGB.
+    fn previous_nchw(src: &[u8], width: usize, height: usize, out: &mut [f32]) {
+        let view = ArrayView::from_shape((height, width, 4), src)
+            .unwrap()
+            .permuted_axes([2, 0, 1]);
+        let rgb = view.slice(s![0..3;-1, .., ..]);
+        let mut out = ArrayViewMut::from_shape((3, height, width), out).unwrap();
+        rgb.axis_iter(Axis(0))
+            .zip(out.axis_iter_mut(Axis(0)))
+            .par_bridge()
+            .for_each(|(old, mut new)| {
+                new.zip_mut_with(&old, |new, old| *new = *old as f32 / 255.0);
+            });
+    }
+
+    fn previous(layout: Layout, src: &[u8], width: usize, height: usize) -> Vec<f32> {
+        let mut dst = vec![f32::NAN; width * height * 3];
+        match layout {
+            Layout::Nhwc => previous_nhwc(src, width, height, &mut dst),
+            Layout::Nchw => previous_nchw(src, width, height, &mut dst),
+        }
+        dst
+    }
+
+    fn convert(convert: &Convert, src: &[u8], width: usize, height: usize, simd: bool) -> Vec<f32> {
+        let mut dst = vec![f32::NAN; width * height * 3];
+        match simd {
+            true => convert.run(src, width, height, &mut dst),
+            false => convert.run_scalar(src, width, height, &mut dst),
+        }
+        dst
+    }
+
+    fn assert_bits_eq(a: &[f32], b: &[f32]) {
+        assert_eq!(a.len(), b.len());
+        for (i, (a, b)) in a.iter().zip(b).enumerate() {
+            assert_eq!(a.to_bits(), b.to_bits(), "value {}: {} != {}", i, a, b);
+        }
+    }
+
+    /// Sizes with and without a SIMD remainder, and more rows than one rayon task.
+    const SIZES: [(usize, usize); 5] = [(1, 1), (3, 2), (256, 256), (257, 65), (640, 480)];
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn matches_previous() {
+        for layout in [Layout::Nhwc, Layout::Nchw] {
+            for (width, height) in SIZES {
+                let src = image(width, height);
+                let expected = previous(layout, &src, width, height);
+                let unit = Convert::unit(layout);
+                assert_bits_eq(&convert(&unit, &src, width, height, true), &expected);
+                assert_bits_eq(&convert(&unit, &src, width, height, false), &expected);
+            }
+        }
+    }
+
+    #[test]
+    #[cfg_attr(miri, ignore)]
+    fn simd_matches_scalar_with_mean_std_and_order() {
+        for layout in [Layout::Nhwc, Layout::Nchw] {
+            for order in [ChannelOrder::Rgb, ChannelOrder::Bgr] {
+                let params = Convert {
+                    layout,
+                    order,
+                    mean: [0.485, 0.456, 0.406],
+                    std: [0.229, 0.224, 0.225],
+                };
+                for (width, height) in SIZES {
+                    let src = image(width, height);
+                    assert_bits_eq(
+                        &convert(&params, &src, width, height, true),
+                        &convert(&params, &src, width, height, false),
+                    );
+                }
+            }
+        }
+    }
+
+    /// `run` takes the AVX2 path where available, check the SSE2 fallback on its own.
+    #[cfg(target_arch = "x86_64")]
+    #[test]
+    fn sse2_matches_scalar() {
+        let src = image(64, 3);
+        for mean in [[0.0; 3], [0.485, 0.456, 0.406]] {
+            let nhwc = Convert {
+                mean,
+                ..Convert::unit(Layout::Nhwc)
+            };
+            let mut dst = vec![f32::NAN; 64 * 3 * 3];
+            unsafe { simd::nhwc_sse2(&nhwc, &src, &mut dst) };
+            assert_bits_eq(&dst, &convert(&nhwc, &src, 64, 3, false));
+
+            let nchw = Convert {
+                mean,
+                ..Convert::unit(Layout::Nchw)
+            };
+            let mut dst = vec![f32::NAN; 64 * 3 * 3];
+            let (c0, rest) = dst.split_at_mut(64 * 3);
+            let (c1, c2) = rest.split_at_mut(64 * 3);
+            unsafe { simd::nchw_sse2(&nchw, &src, [c0, c1, c2]) };
+            assert_bits_eq(&dst, &convert(&nchw, &src, 64, 3, false));
+        }
+    }
+
+    #[test]
+    fn applies_order_mean_and_std() {
+        // one BGRA pixel
+        let src = [10, 20, 30, 255];
+        let params = Convert {
+            layout: Layout::Nhwc,
+            order: ChannelOrder::Bgr,
+            mean: [0.5, 0.0, 0.0],
+            std: [2.0, 1.0, 0.5],
+        };
+        let out = convert(&params, &src, 1, 1, true);
+        assert_eq!(
+            out,
+            [(10.0 / 255.0 - 0.5) / 2.0, 20.0 / 255.0, 30.0 / 255.0 / 0.5]
+        );
+
+        let rgb = convert(&Convert::unit(Layout::Nchw), &src, 1, 1, true);
+        assert_eq!(rgb, [30.0 / 255.0, 20.0 / 255.0, 10.0 / 255.0]);
+    }
+
+    /// Small enough for `cargo miri test writes_every_value`, which checks the unsafe SIMD
+    /// loads and stores stay in bounds. Covers a SIMD remainder and a split into rayon tasks.
+    #[test]
+    fn writes_every_value() {
+        for layout in [Layout::Nhwc, Layout::Nchw] {
+            let params = Convert {
+                mean: [0.5; 3],
+                ..Convert::unit(layout)
+            };
+            for (width, height) in [(1, 1), (11, 2), (3, Convert::ROWS_PER_TASK + 1)] {
+                let src = image(width, height);
+                let simd = convert(&params, &src, width, height, true);
+                assert!(
+                    simd.iter().all(|v| !v.is_nan()),
+                    "{:?} {}x{}",
+                    layout,
+                    width,
+                    height
+                );
+                assert_bits_eq(&simd, &convert(&params, &src, width, height, false));
+            }
+        }
+    }
+
+    /// `cargo test --release bench_convert -- --ignored --nocapture`
+    #[test]
+    #[ignore]
+    fn bench_convert() {
+        const RUNS: u32 = 200;
+        let time = |name: &str, f: &mut dyn FnMut()| {
+            f();
+            let start = Instant::now();
+            for _ in 0..RUNS {
+                f();
+            }
+            let per_run = start.elapsed() / RUNS;
+            println!("{:>24}: {:>8.1} us", name, per_run.as_secs_f64() * 1e6);
+        };
+        for (width, height, layout) in [(256, 256, Layout::Nhwc), (640, 640, Layout::Nchw)] {
+            println!("{}x{} {:?}", width, height, layout);
+            let src = image(width, height);
+            let params = Convert::unit(layout);
+            let mut dst = vec![0.0; width * height * 3];
+            time("previous", &mut || {
+                match layout {
+                    Layout::Nhwc => previous_nhwc(&src, width, height, &mut dst),
+                    Layout::Nchw => previous_nchw(&src, width, height, &mut dst),
+                }
+                std::hint::black_box(&dst);
+            });
+            time("scalar", &mut || {
+                params.run_scalar(&src, width, height, &mut dst);
+                std::hint::black_box(&dst);
+            });
+            time("simd", &mut || {
+                params.run(&src, width, height, &mut dst);
+                std::hint::black_box(&dst);
+            });
+        }
+    }
+}
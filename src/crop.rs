@@ -0,0 +1,109 @@
+//! Rotated square face crops and the affine warp that feeds them to the landmark model.
+
+use fast_image_resize::PixelType;
+use fast_image_resize::images::ImageRef;
+use ndarray::{ArrayViewMut3, Axis};
+use nvgx::{Point, Rect, Transform};
+use rayon::iter::{ParallelBridge, ParallelIterator};
+
+/// Square region of the source image rotated by `angle` radians around its centre, in source
+/// pixels. Positive angles turn clockwise on screen.
+#[derive(Debug, Clone, Copy)]
+pub struct FaceCrop {
+    pub center: Point,
+    pub size: f32,
+    pub angle: f32,
+}
+
+impl FaceCrop {
+    /// Angles below this are treated as axis aligned.
+    const MIN_ANGLE: f32 = 1e-3;
+
+    /// Axis aligned crop covering the square `rect`.
+    pub fn from_rect(rect: Rect) -> Self {
+        Self {
+            center: Point::new(
+                rect.xy.x + rect.size.width / 2.0,
+                rect.xy.y + rect.size.height / 2.0,
+            ),
+            size: f32::max(rect.size.width, rect.size.height),
+            angle: 0.0,
+        }
+    }
+
+    /// The crop square before rotation.
+    pub fn rect(&self) -> Rect {
+        let half = self.size / 2.0;
+        Rect {
+            xy: (self.center.x - half, self.center.y - half).into(),
+            size: (self.size, self.size).into(),
+        }
+    }
+
+    pub fn is_rotated(&self) -> bool {
+        self.angle.abs() > Self::MIN_ANGLE
+    }
+
+    /// Maps pixel coordinates of a `dst_size` square image of the crop onto source pixels.
+    pub fn source_transform(&self, dst_size: f32) -> Transform {
+        let half = dst_size / 2.0;
+        let scale = self.size / dst_size;
+        Transform::translate(-half, -half)
+            * Transform::scale(scale, scale)
+            * Transform::rotate(self.angle)
+            * Transform::translate(self.center.x, self.center.y)
+    }
+}
+
+/// Roll of the line through both eyes, 0 for a level face. `left_eye` is the eye on the left of
+/// the image.
+pub fn roll_angle(left_eye: Point, right_eye: Point) -> f32 {
+    f32::atan2(right_eye.y - left_eye.y, right_eye.x - left_eye.x)
+}
+
+/// Samples `crop` from a BGRA image into an `[height, width, 3]` RGB slot scaled to 0..1, with
+/// bilinear filtering. Pixels outside the image are black.
+pub fn warp_crop(
+    src_image: &ImageRef,
+    crop: &FaceCrop,
+    mut dst: ArrayViewMut3<f32>,
+) -> anyhow::Result<()> {
+    if src_image.pixel_type() != PixelType::U8x4 {
+        anyhow::bail!("face crops need a BGRA image");
+    }
+    let (width, height) = (src_image.width() as i64, src_image.height() as i64);
+    // rows are tightly packed, pixels are read straight from the frame
+    let buffer = src_image.buffer();
+    let pixel = |x: i64, y: i64| -> [f32; 3] {
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return [0.0; 3];
+        }
+        let i = (y * width + x) as usize * 4;
+        let (b, g, r) = (buffer[i], buffer[i + 1], buffer[i + 2]);
+        [r as f32, g as f32, b as f32]
+    };
+
+    let dst_size = dst.dim().1 as f32;
+    let transform = crop.source_transform(dst_size);
+    dst.axis_iter_mut(Axis(0))
+        .enumerate()
+        .par_bridge()
+        .for_each(|(v, mut row)| {
+            for (u, mut out) in row.axis_iter_mut(Axis(0)).enumerate() {
+                // sample at the centre of the destination pixel
+                let p = transform.transform_point(Point::new(u as f32 + 0.5, v as f32 + 0.5));
+                let (x, y) = (p.x - 0.5, p.y - 0.5);
+                let (x0, y0) = (x.floor(), y.floor());
+                let (fx, fy) = (x - x0, y - y0);
+                let (x0, y0) = (x0 as i64, y0 as i64);
+                let (p00, p10) = (pixel(x0, y0), pixel(x0 + 1, y0));
+                let (p01, p11) = (pixel(x0, y0 + 1), pixel(x0 + 1, y0 + 1));
+                for c in 0..3 {
+                    let top = p00[c] + (p10[c] - p00[c]) * fx;
+                    let bottom = p01[c] + (p11[c] - p01[c]) * fx;
+                    out[c] = (top + (bottom - top) * fy) / 255.0;
+                }
+            }
+        });
+    Ok(())
+}
@@ -1,5 +1,8 @@
 use nvgx::{Context, RendererDevice};
 use winit;
+#[cfg(windows)]
+use winit::platform::windows::WindowAttributesExtWindows;
+use winit::window::WindowAttributes;
 
 const DEFAULT_SIZE: (i32, i32) = (640, 480);
 pub const FONT_PATH: &str = "Roboto-Bold.ttf";
@@ -20,6 +23,19 @@ cfg_if::cfg_if! {
     }
 }
 
+/// Platform specific window options shared by the runners.
+#[cfg(windows)]
+fn platform_attributes(attributes: WindowAttributes) -> WindowAttributes {
+    // drag and drop initialises OLE on the window thread, which clashes with the COM
+    // apartment the camera needs
+    attributes.with_drag_and_drop(false)
+}
+
+#[cfg(not(windows))]
+fn platform_attributes(attributes: WindowAttributes) -> WindowAttributes {
+    attributes
+}
+
 pub trait Demo<R: RendererDevice> {
     fn init(&mut self, ctx: &mut Context<R>, _scale_factor: f32) -> anyhow::Result<()> {
         ctx.create_font_from_file("roboto", FONT_PATH)?;
@@ -42,5 +58,9 @@ pub trait Demo<R: RendererDevice> {
     fn key_event(&mut self, _key: winit::keyboard::KeyCode, _state: winit::event::ElementState) {}
 
     fn mouse_wheel(&mut self, _delta: winit::event::MouseScrollDelta) {}
-}
 
+    /// Called once the window closed.
+    fn exit(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
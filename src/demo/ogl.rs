@@ -11,7 +11,6 @@ use winit::application::ApplicationHandler;
 use winit::event::{KeyEvent, WindowEvent};
 use winit::event_loop::{ActiveEventLoop, EventLoop};
 use winit::keyboard::{Key, NamedKey, PhysicalKey};
-use winit::platform::windows::WindowAttributesExtWindows;
 use winit::window::{Window, WindowAttributes};
 
 use glutin::config::{Config, ConfigTemplateBuilder, GetGlConfig};
@@ -22,7 +21,7 @@ use glutin::display::GetGlDisplay;
 use glutin::prelude::*;
 use glutin::surface::{Surface, WindowSurface};
 
-use glutin_winit::{DisplayBuilder, GlWindow};
+use glutin_winit::{ApiPreference, DisplayBuilder, GlWindow};
 
 use tracy_client::{frame_mark, span};
 
@@ -32,14 +31,14 @@ pub fn run<D: Demo<nvgx_ogl::Renderer>>(demo: D, title: &str) {
         .with_alpha_size(8)
         .with_transparency(true);
 
-    let attributes = window_attributes()
-        .with_drag_and_drop(false)
-        .with_title(format!("{} (OpenGL)", title));
+    let attributes = window_attributes().with_title(format!("{} (OpenGL)", title));
 
     let mut app = App::new(template, attributes, demo);
     event_loop.run_app(&mut app).unwrap();
 
+    let exited = app.demo.exit();
     app.exit_state.unwrap();
+    exited.unwrap();
 }
 
 enum GlDisplayCreationState {
@@ -74,7 +73,9 @@ impl<D: Demo<nvgx_ogl::Renderer>> App<D> {
             template,
             demo,
             gl_display: GlDisplayCreationState::Builder(
-                DisplayBuilder::new().with_window_attributes(Some(attributes)),
+                DisplayBuilder::new()
+                    .with_preference(api_preference())
+                    .with_window_attributes(Some(attributes)),
             ),
             exit_state: Ok(()),
             gl_context: None,
@@ -307,12 +308,24 @@ impl<D: Demo<nvgx_ogl::Renderer>> ApplicationHandler for App<D> {
 }
 
 fn window_attributes() -> WindowAttributes {
-    Window::default_attributes()
-        .with_transparent(true)
-        .with_inner_size(winit::dpi::LogicalSize::new(
-            super::DEFAULT_SIZE.0,
-            super::DEFAULT_SIZE.1,
-        ))
+    super::platform_attributes(
+        Window::default_attributes()
+            .with_transparent(true)
+            .with_inner_size(winit::dpi::LogicalSize::new(
+                super::DEFAULT_SIZE.0,
+                super::DEFAULT_SIZE.1,
+            )),
+    )
+}
+
+/// WGL on Windows. Elsewhere EGL, which works on both X11 and Wayland, GLX is only the
+/// fallback.
+fn api_preference() -> ApiPreference {
+    if cfg!(windows) {
+        ApiPreference::FallbackEgl
+    } else {
+        ApiPreference::PreferEgl
+    }
 }
 
 fn create_gl_context(window: &Window, gl_config: &Config) -> NotCurrentContext {
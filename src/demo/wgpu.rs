@@ -11,7 +11,6 @@ use winit::{
     event::{KeyEvent, WindowEvent},
     event_loop::EventLoop,
     keyboard::{Key, NamedKey, PhysicalKey},
-    platform::windows::WindowAttributesExtWindows,
     window::{Window, WindowAttributes},
 };
 
@@ -22,11 +21,13 @@ pub fn run<D: Demo<nvgx_wgpu::Renderer>>(demo: D, title: &str) {
             super::DEFAULT_SIZE.0,
             super::DEFAULT_SIZE.1,
         ))
-        .with_drag_and_drop(false)
         .with_title(format!("{} (WGPU)", title));
+    let attributes = super::platform_attributes(attributes);
     let mut app = App::new(demo, attributes);
     event_loop.run_app(&mut app).expect("failed to run app");
+    let exited = app.demo.exit();
     app.exit_state.unwrap();
+    exited.unwrap();
 }
 
 struct App<D: Demo<nvgx_wgpu::Renderer>> {
@@ -1,17 +1,16 @@
 use std::path::Path;
 
-use fast_image_resize::{IntoImageView, ResizeOptions, Resizer, images::Image};
-use ndarray::{Array4, ArrayView, IndexLonger};
-use ndarray::{Axis, s};
-use nvgx::Rect;
-use ort::{
-    execution_providers::{CPUExecutionProvider, DirectMLExecutionProvider},
-    inputs,
-    session::Session,
-};
-use rayon::iter::{ParallelBridge, ParallelIterator};
+use fast_image_resize::images::{Image, ImageRef};
+use fast_image_resize::{PixelType, ResizeOptions, Resizer};
+use ndarray::{ArrayViewMut3, Axis};
+use nvgx::Point;
+use ort::session::{Session, SessionInputValue};
+use ort::value::Tensor;
 use tracy_client::span;
 
+use crate::convert::{Convert, Layout};
+use crate::crop::{FaceCrop, warp_crop};
+use crate::model::{self, Dim, ModelError, ModelSpec, SessionOptions, Shape, TensorSpec};
 use crate::utils::sigmoid;
 
 pub struct FaceLandmarkResult {
@@ -22,114 +21,170 @@ pub struct FaceLandmarkResult {
 
 pub struct FaceLandmark {
     session: Session,
+    pub provider: &'static str,
+    /// The model accepts more than one crop per run.
+    dynamic_batch: bool,
     resizer: Resizer,
+    /// Resize target for axis aligned crops, reused across crops and frames.
+    crop_image: Image<'static>,
+    /// Model inputs by batch size minus one, allocated when a batch size is first used.
+    inputs: Vec<Tensor<f32>>,
 }
 
 impl FaceLandmark {
     pub const INPUT_SIZE: usize = 256;
     pub const MARKS_NUM: usize = 478;
+    /// Landmarks matching the detector keypoints: eye centres, nose tip and mouth corners, in
+    /// image left to right order.
+    pub const KEYPOINTS: [usize; 5] = [468, 473, 1, 61, 291];
+    const CONVERT: Convert = Convert::unit(Layout::Nhwc);
 
-    pub fn new<P: AsRef<Path>>(model: P) -> anyhow::Result<Self> {
-        let session = Session::builder()?
-            .with_execution_providers([
-                DirectMLExecutionProvider::default().build(),
-                CPUExecutionProvider::default().build(),
-            ])?
-            .with_inter_threads(4)?
-            .with_parallel_execution(true)?
-            .with_optimization_level(ort::session::builder::GraphOptimizationLevel::Level3)?
-            .commit_from_file(model)
-            .expect("faceland mark model");
+    pub const SPEC: ModelSpec = ModelSpec {
+        name: "face landmark",
+        // [batch, height, width, 3]
+        inputs: &[TensorSpec::f32(Shape::Dims(&[
+            Dim::Any,
+            Dim::Exact(Self::INPUT_SIZE as i64),
+            Dim::Exact(Self::INPUT_SIZE as i64),
+            Dim::Exact(3),
+        ]))
+        .named("input_12")],
+        outputs: &[
+            // landmarks, reshaped to [MARKS_NUM, 3]
+            TensorSpec::f32(Shape::Elements(Self::MARKS_NUM as i64 * 3)).named("Identity"),
+            // face presence score logit
+            TensorSpec::f32(Shape::Dims(&[Dim::Any, Dim::Any, Dim::Any, Dim::Any]))
+                .named("Identity_1"),
+            // tongue out score
+            TensorSpec::f32(Shape::Dims(&[Dim::Any, Dim::Any])).named("Identity_2"),
+        ],
+    };
+
+    pub fn new<P: AsRef<Path>>(model: P, options: &SessionOptions) -> Result<Self, ModelError> {
+        let model = model::load(&Self::SPEC, model, options)?;
+        let dynamic_batch = model.inputs[0].dims[0] < 0;
         Ok(Self {
-            session,
+            session: model.session,
+            provider: model.provider,
+            dynamic_batch,
             resizer: Resizer::new(),
+            crop_image: Image::new(
+                Self::INPUT_SIZE as u32,
+                Self::INPUT_SIZE as u32,
+                PixelType::U8x4,
+            ),
+            inputs: Vec::new(),
         })
     }
 
-    pub fn proc_image(
+    /// Runs the model on several face crops of the same image, returning one result per crop.
+    ///
+    /// The crops go through the model as one `[N, 256, 256, 3]` batch when its batch dimension
+    /// is dynamic, otherwise one at a time.
+    pub fn proc_images(
         &mut self,
-        src_image: &impl IntoImageView,
-        face_rect: Rect,
-        scale_to: (f32, f32),
-    ) -> anyhow::Result<Option<FaceLandmarkResult>> {
-        let _flm = span!("FacelandMark Face");
+        src_image: &ImageRef,
+        crops: &[FaceCrop],
+    ) -> anyhow::Result<Vec<Option<FaceLandmarkResult>>> {
+        let _flm = span!("FacelandMark Faces");
         _flm.emit_color(0xfe602f);
-        let input_array: Array4<f32> = {
-            let _preproc = span!("Pre Proc");
-            let mut dst_img = Image::new(
-                Self::INPUT_SIZE as u32,
-                Self::INPUT_SIZE as u32,
-                fast_image_resize::PixelType::U8x4,
-            );
-
-            self.resizer.resize(
-                src_image,
-                &mut dst_img,
-                &ResizeOptions::new().crop(
-                    face_rect.xy.x as f64,
-                    face_rect.xy.y as f64,
-                    face_rect.size.width as f64,
-                    face_rect.size.height as f64,
-                ),
-            )?;
-
-            let array_view = ArrayView::from_shape(
-                (1, Self::INPUT_SIZE, Self::INPUT_SIZE, 4),
-                dst_img.buffer(),
-            )?;
+        if self.dynamic_batch || crops.len() <= 1 {
+            return self.run_batch(src_image, crops);
+        }
+        let mut results = Vec::with_capacity(crops.len());
+        for &crop in crops {
+            results.extend(self.run_batch(src_image, &[crop])?);
+        }
+        Ok(results)
+    }
 
-            let rgb_array_view = array_view.slice(s![.., .., .., 0..3;-1]);
-            let mut input_array: Array4<f32> =
-                unsafe { Array4::uninit(rgb_array_view.dim()).assume_init() };
-            rgb_array_view
-                .axis_iter(Axis(1))
-                .zip(input_array.axis_iter_mut(Axis(1)))
-                .par_bridge()
-                .for_each(|(old, mut new)| {
-                    new.zip_mut_with(&old, |new, old| *new = *old as f32 / 255.0);
-                });
+    fn run_batch(
+        &mut self,
+        src_image: &ImageRef,
+        crops: &[FaceCrop],
+    ) -> anyhow::Result<Vec<Option<FaceLandmarkResult>>> {
+        let batch = crops.len();
+        if batch == 0 {
+            return Ok(Vec::new());
+        }
+        {
+            let _preproc = span!("Pre Proc");
+            while self.inputs.len() < batch {
+                let size = self.inputs.len() + 1;
+                self.inputs.push(model::input_tensor([
+                    size,
+                    Self::INPUT_SIZE,
+                    Self::INPUT_SIZE,
+                    3,
+                ])?);
+            }
+            let input_len = Self::INPUT_SIZE * Self::INPUT_SIZE * 3;
+            let inputs = self.inputs[batch - 1].extract_raw_tensor_mut().1;
+            let dst_img = &mut self.crop_image;
+            for (crop, input) in crops.iter().zip(inputs.chunks_exact_mut(input_len)) {
+                if crop.is_rotated() {
+                    let input =
+                        ArrayViewMut3::from_shape((Self::INPUT_SIZE, Self::INPUT_SIZE, 3), input)?;
+                    warp_crop(src_image, crop, input)?;
+                    continue;
+                }
+                let face_rect = crop.rect();
+                self.resizer.resize(
+                    src_image,
+                    dst_img,
+                    &ResizeOptions::new().crop(
+                        face_rect.xy.x as f64,
+                        face_rect.xy.y as f64,
+                        face_rect.size.width as f64,
+                        face_rect.size.height as f64,
+                    ),
+                )?;
 
-            input_array
-        };
+                // [h, w, BGRA] -> [h, w, RGB]
+                Self::CONVERT.run(dst_img.buffer(), Self::INPUT_SIZE, Self::INPUT_SIZE, input);
+            }
+        }
         let outputs = {
             let _inference = span!("Inference");
-            self.session.run(inputs![input_array.view()].unwrap())?
+            let input = self.inputs[batch - 1].view();
+            self.session.run([SessionInputValue::from(input)])?
         };
         {
             let _post_proc = span!("Post Proc");
-            let score = sigmoid(
-                outputs[1]
-                    .try_extract_tensor::<f32>()
-                    .unwrap()
-                    .index([0, 0, 0, 0]),
-            );
-            if score < 0.5 {
-                return Ok(None);
-            }
-            let tongue = *(outputs[2]
-                .try_extract_tensor::<f32>()
-                .unwrap()
-                .index([0, 0]));
-
-            let marks = outputs[0]
-                .try_extract_tensor::<f32>()
-                .unwrap()
-                .into_shape_with_order((Self::MARKS_NUM, 3))
-                .unwrap();
-            let pos_scale = (
-                scale_to.0 / Self::INPUT_SIZE as f32,
-                scale_to.1 / Self::INPUT_SIZE as f32,
-            );
+            // split every output into one row per batch entry
+            let marks = outputs[0].try_extract_tensor::<f32>()?;
+            let marks = marks.into_shape_with_order((batch, Self::MARKS_NUM, 3))?;
+            let scores = outputs[1].try_extract_tensor::<f32>()?;
+            let scores = scores.to_shape((batch, scores.len() / batch))?;
+            let tongues = outputs[2].try_extract_tensor::<f32>()?;
+            let tongues = tongues.to_shape((batch, tongues.len() / batch))?;
 
-            let points: Vec<(f32, f32, f32)> = marks
-                .axis_iter(Axis(0))
-                .map(|p| (pos_scale.0 * p[0], pos_scale.1 * p[1], p[2]))
+            let results = crops
+                .iter()
+                .enumerate()
+                .map(|(i, crop)| {
+                    let score = sigmoid(&scores[[i, 0]]);
+                    if score < 0.5 {
+                        return None;
+                    }
+                    // model input pixels back to the source image
+                    let to_source = crop.source_transform(Self::INPUT_SIZE as f32);
+                    let points: Vec<(f32, f32, f32)> = marks
+                        .index_axis(Axis(0), i)
+                        .axis_iter(Axis(0))
+                        .map(|p| {
+                            let xy = to_source.transform_point(Point::new(p[0], p[1]));
+                            (xy.x, xy.y, p[2])
+                        })
+                        .collect();
+                    Some(FaceLandmarkResult {
+                        points,
+                        score,
+                        tongue: tongues[[i, 0]],
+                    })
+                })
                 .collect();
-            return Ok(Some(FaceLandmarkResult {
-                points,
-                score,
-                tongue,
-            }));
+            Ok(results)
         }
     }
 }
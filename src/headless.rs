@@ -0,0 +1,67 @@
+use std::time::Duration;
+
+use crate::measure_time;
+use crate::pipeline::FacePipeline;
+use crate::sink::ResultSink;
+use crate::source::FrameSource;
+
+/// Runs the face pipeline over every frame of `source` without creating a window or touching
+/// the GPU. Results go to `sink` when given, otherwise a summary line per frame is printed.
+pub fn run(
+    mut source: Box<dyn FrameSource>,
+    mut pipeline: FacePipeline,
+    mut sink: Option<Box<dyn ResultSink>>,
+) -> anyhow::Result<()> {
+    let providers = pipeline.providers();
+    eprintln!("detector on {}, landmarks on {}", providers.0, providers.1);
+    source.start()?;
+
+    let mut frames = 0u64;
+    let mut inference_time = Duration::ZERO;
+    loop {
+        let mut processed = None;
+        source.with_next_frame(&mut |frame| {
+            processed = Some(measure_time!({ pipeline.process(frame)? }));
+            Ok(())
+        })?;
+        let Some((result, duration)) = processed else {
+            break;
+        };
+        frames += 1;
+        inference_time += duration;
+
+        if let Some(sink) = sink.as_mut() {
+            sink.write(&result)?;
+            continue;
+        }
+        let landmarks: Vec<String> = result
+            .landmarks
+            .iter()
+            .map(|face| {
+                format!(
+                    ", #{} score {:.2} tongue {:.2}",
+                    face.id, face.landmarks.score, face.landmarks.tongue
+                )
+            })
+            .collect();
+        println!(
+            "frame {}: {} faces{} ({:.1} ms)",
+            result.index,
+            result.faces.len(),
+            landmarks.concat(),
+            duration.as_secs_f32() * 1000.0
+        );
+    }
+    if let Some(sink) = sink.as_mut() {
+        sink.flush()?;
+    }
+
+    if frames > 0 {
+        eprintln!(
+            "processed {} frames, {:.1} ms average inference",
+            frames,
+            inference_time.as_secs_f32() * 1000.0 / frames as f32
+        );
+    }
+    Ok(())
+}
@@ -1,21 +1,53 @@
+mod config;
+mod convert;
+mod crop;
 mod demo;
 mod face_landmark;
+mod headless;
+mod model;
 mod perf;
+mod pipeline;
+mod recording;
+mod sink;
+mod smoothing;
+mod source;
+mod tracker;
 mod utils;
+mod worker;
 mod yolov5_face;
 
+use std::sync::Arc;
 use std::time::Instant;
 
 use anyhow::Error;
-use face_landmark::FaceLandmark;
-use fast_image_resize::{PixelType, images::ImageRef};
+use config::Config;
 use num_traits::AsPrimitive;
 use nvgx::*;
 use perf::PerfGraph;
-use utils::scale_rect;
-use yolov5_face::YoloV5Face;
+use pipeline::FacePipeline;
+use recording::RecordingReader;
+use sink::ResultSink;
+use smoothing::LandmarkSmoother;
+use source::{BlankSource, CameraSource, FrameSource};
+use worker::{Analysis, AnalyzedFrame, CapturedFrame, InferenceWorker, OpenSource};
+use yolov5_face::Preprocess;
 
 use tracy_client::{Client, span};
+use winit::event::ElementState;
+use winit::keyboard::KeyCode;
+
+/// Frames skipped by PageUp/PageDown when playing back a file source.
+const SEEK_STEP: u64 = 30;
+
+/// Landmark colours, picked by face id.
+const FACE_COLORS: [(u8, u8, u8); 6] = [
+    (0x30, 0xc8, 0xff),
+    (0xff, 0xa0, 0x30),
+    (0x60, 0xe0, 0x60),
+    (0xff, 0x60, 0xc0),
+    (0xc0, 0x90, 0xff),
+    (0xf0, 0xf0, 0x50),
+];
 
 #[inline]
 fn padding_fit_img<N1: AsPrimitive<f32>, N2: AsPrimitive<f32>>(
@@ -32,57 +64,32 @@ fn padding_fit_img<N1: AsPrimitive<f32>, N2: AsPrimitive<f32>>(
     }
 }
 
-#[inline]
-fn mk_face_land_mark_crop_from_bbox<N: AsPrimitive<f32>>(
-    bbox: Rect,
-    img_size: (N, N),
-    margin: f32,
-) -> (Rect, Point) {
-    let img_size: (f32, f32) = (img_size.0.as_(), img_size.1.as_());
-    let bbox = scale_rect(bbox, img_size);
-    let max_size = f32::max(bbox.size.width, bbox.size.height) * margin;
-    let max_size = f32::min(max_size, f32::min(img_size.0, img_size.1));
-    let half_max_size = max_size / 2.0;
-    let center = (
-        bbox.xy.x + bbox.size.width / 2.0,
-        bbox.xy.y + bbox.size.height / 2.0,
-    );
-
-    let mut left = f32::max(0.0, center.0 - half_max_size);
-    let right = left + max_size;
-    if right > img_size.0 {
-        left = img_size.0 - max_size;
-    }
-    let mut top = f32::max(0.0, center.1 - half_max_size);
-    let bottom = top + max_size;
-    if bottom > img_size.1 {
-        top = img_size.1 - max_size;
-    }
-
-    return (
-        Rect {
-            xy: (left, top).into(),
-            size: (max_size, max_size).into(),
-        },
-        center.into(),
-    );
-}
-
 struct DemoDraw {
     img_size: Option<(ImageId, (u32, u32))>,
-    camera: kamera::Camera,
-    yolov5n_face: YoloV5Face,
-    face_land_mark: FaceLandmark,
+    worker: InferenceWorker,
+    /// Newest captured frame, drawn until a newer one arrives.
+    frame: Option<Arc<CapturedFrame>>,
+    /// Newest result, usually of an older frame than the one drawn.
+    result: Option<AnalyzedFrame>,
+    sink: Option<Box<dyn ResultSink>>,
+    /// How frames are fitted into the detector, a centre crop masks the rest of the frame.
+    preprocess: Preprocess,
+    /// Highest frame index written to the sink, results up to it aren't written again after
+    /// seeking back.
+    written: Option<u64>,
     prev_time: Instant,
     frame_time_graph: PerfGraph<64>,
     inference_time_graph: PerfGraph<64>,
+    latency_graph: PerfGraph<64>,
+    /// Stage timings, drawn once a staged analysis reported them.
+    detection_time_graph: PerfGraph<64>,
+    landmark_time_graph: PerfGraph<64>,
     render_time_graph: PerfGraph<64>,
 }
 
 impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
     fn init(&mut self, ctx: &mut Context<R>, _scale_factor: f32) -> Result<(), Error> {
         ctx.create_font_from_file("roboto", demo::FONT_PATH)?;
-        self.camera.start();
         Ok(())
     }
 
@@ -92,15 +99,42 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
         self.render_time_graph
             .update((Instant::now() - self.prev_time).as_secs_f32());
 
-        let frame = {
-            let _camera = span!("Camera");
-            let Some(frame) = self.camera.wait_for_frame() else {
-                return Ok(());
-            };
-            frame
+        self.worker.check()?;
+        let new_frame = match self.worker.latest_frame() {
+            Some(frame) => {
+                self.frame = Some(frame);
+                true
+            }
+            None => false,
+        };
+        for analyzed in self.worker.results() {
+            let index = analyzed.result.index;
+            if self.written.is_none_or(|written| index > written) {
+                if let Some(sink) = self.sink.as_mut() {
+                    sink.write(&analyzed.result)?;
+                }
+                self.written = Some(index);
+            }
+            self.inference_time_graph
+                .update(analyzed.inference_time.as_secs_f32());
+            self.latency_graph
+                .update(analyzed.captured.elapsed().as_secs_f32());
+            if let Some(stages) = analyzed.stage_times {
+                self.detection_time_graph
+                    .update(stages.detection.as_secs_f32());
+                self.landmark_time_graph
+                    .update(stages.landmark.as_secs_f32());
+            }
+            self.result = Some(analyzed);
+        }
+        let Some(frame) = self.frame.clone() else {
+            return Ok(());
         };
-        let frame_data = frame.data();
-        let cap_size = frame.size_u32();
+        let landmarks = match &self.result {
+            Some(analyzed) => &analyzed.result.landmarks[..],
+            None => &[],
+        };
+        let cap_size = frame.size;
 
         let cap_size_f = (cap_size.0 as f32, cap_size.1 as f32);
         let img_display_size = padding_fit_img(cap_size_f, (width, height));
@@ -111,55 +145,7 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
         )
             .into();
 
-        let src_img = ImageRef::new(
-            cap_size.0,
-            cap_size.1,
-            frame_data.data_u8(),
-            PixelType::U8x4,
-        )?;
-
-        let ((faces, face_land_marks), inference_time) = measure_time!({
-            let faces = self.yolov5n_face.proc_image(&src_img, 0.6, 0.5)?;
-
-            let face_land_marks = {
-                let max_conf_face = faces.iter().max_by(|a, b| a.conf.total_cmp(&b.conf));
-                if let Some(face) = max_conf_face {
-                    use std::ops::Add;
-                    let yolov5_square_width_img = f32::min(cap_size_f.0, cap_size_f.1);
-                    let yolov5_crop_img_size = (yolov5_square_width_img, yolov5_square_width_img);
-                    let yolov5_crop_img_offset = (
-                        (cap_size_f.0 - yolov5_crop_img_size.0) / 2.0,
-                        (cap_size_f.1 - yolov5_crop_img_size.1) / 2.0,
-                    );
-                    let (relative_img_crop, _) =
-                        mk_face_land_mark_crop_from_bbox(face.bbox, yolov5_crop_img_size, 1.5);
-                    let abs_img_crop = Rect {
-                        xy: relative_img_crop.xy.add(&yolov5_crop_img_offset.into()),
-                        size: relative_img_crop.size,
-                    };
-
-                    let face_land_mark_display_size = (
-                        relative_img_crop.size.width * img_display_scale,
-                        relative_img_crop.size.height * img_display_scale,
-                    );
-                    let result = self.face_land_mark.proc_image(
-                        &src_img,
-                        abs_img_crop,
-                        face_land_mark_display_size,
-                    )?;
-
-                    let display_rect = Rect {
-                        xy: abs_img_crop.xy.mul(img_display_scale),
-                        size: face_land_mark_display_size.into(),
-                    };
-
-                    result.map(|v| (v, display_rect))
-                } else {
-                    None
-                }
-            };
-            (faces, face_land_marks)
-        });
+        let to_display = |p: Point| Point::new(p.x * img_display_scale, p.y * img_display_scale);
 
         {
             let img = {
@@ -167,7 +153,9 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
                 _update_img.emit_color(0xff2020);
                 let img_update = match self.img_size {
                     Some((img, img_size)) if img_size == cap_size => {
-                        ctx.update_image(img, frame_data.data_u8(), None)?;
+                        if new_frame {
+                            ctx.update_image(img, &frame.data, None)?;
+                        }
                         Some(img)
                     }
                     Some((img, _)) => {
@@ -184,7 +172,7 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
                             cap_size.1,
                             TextureType::BGRA,
                             ImageFlags::REPEATX | ImageFlags::REPEATY,
-                            Some(frame_data.data_u8()),
+                            Some(&frame.data),
                         )?;
                         img
                     }
@@ -213,16 +201,15 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
             ctx.reset_transform();
             ctx.translate(img_display_offset.x, img_display_offset.y);
 
-            // draw yolov5 area
-            // yolov5_display_width = min(cap_size_f.0, cap_size_f.1) * img_display_scale
-            let yolov5_display_width = f32::min(img_display_size.0, img_display_size.1);
-            let yolov5_display_offset: Point = (
-                (img_display_size.0 - yolov5_display_width) / 2.0,
-                (img_display_size.1 - yolov5_display_width) / 2.0,
-            )
-                .into();
-            {
-                // camera yolov5 mask
+            if self.preprocess == Preprocess::CenterCrop {
+                // shade the sides the detector doesn't search
+                // yolov5_display_width = min(cap_size_f.0, cap_size_f.1) * img_display_scale
+                let yolov5_display_width = f32::min(img_display_size.0, img_display_size.1);
+                let yolov5_display_offset: Point = (
+                    (img_display_size.0 - yolov5_display_width) / 2.0,
+                    (img_display_size.1 - yolov5_display_width) / 2.0,
+                )
+                    .into();
                 ctx.begin_path();
                 ctx.rect((0.0, 0.0, img_display_size.0, img_display_size.1));
                 ctx.rect(Rect {
@@ -233,44 +220,36 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
                 ctx.fill_paint(nvgx::Color::rgba(1.0, 1.0, 1.0, 0.2));
                 ctx.fill()?;
             }
-            if false {
-                // draw face rect
-                ctx.save();
-                ctx.stroke_paint(nvgx::Color::rgb_i(0x00, 0xBF, 0xA8));
-                ctx.translate(yolov5_display_offset.x, yolov5_display_offset.y);
-                for face in faces {
-                    ctx.begin_path();
-                    ctx.rounded_rect(
-                        scale_rect(face.bbox, (yolov5_display_width, yolov5_display_width)),
-                        10.0,
-                    );
-                    ctx.stroke()?;
-                }
-                ctx.restore();
-            }
 
-            if let Some((face_land_marks, rect)) = face_land_marks {
+            for face in landmarks {
+                let center = to_display(face.crop.center);
+                let size = face.crop.size * img_display_scale;
+                let (r, g, b) = FACE_COLORS[face.id as usize % FACE_COLORS.len()];
                 ctx.save();
-                ctx.stroke_paint(nvgx::Color::rgb_i(0x20, 0xBF, 0xA8));
+                ctx.translate(center.x, center.y);
+                ctx.rotate(face.crop.angle);
+                ctx.stroke_paint(nvgx::Color::rgb_i(r, g, b));
                 ctx.begin_path();
-                ctx.rounded_rect(rect, 10.0);
+                ctx.rounded_rect((-size / 2.0, -size / 2.0, size, size), 10.0);
                 ctx.stroke()?;
                 ctx.fill_paint(nvgx::Color::rgb_i(0xFF, 0x64, 0x64));
                 ctx.font_size(30.0);
                 ctx.text(
-                    rect.xy,
+                    (-size / 2.0, -size / 2.0),
                     &format!(
-                        "score: {:.1} tongue:{:.1}",
-                        (face_land_marks.score),
-                        face_land_marks.tongue
+                        "#{} score: {:.1} tongue:{:.1}",
+                        face.id,
+                        (face.landmarks.score),
+                        face.landmarks.tongue
                     ),
                 )?;
-                ctx.translate(rect.xy.x, rect.xy.y);
+                ctx.restore();
+                ctx.save();
                 {
                     ctx.begin_path();
-                    ctx.fill_paint(Color::rgba_i(0x30, 0xc8, 0xff, 0x80));
-                    for point in face_land_marks.points {
-                        ctx.circle((point.0, point.1), 3.0);
+                    ctx.fill_paint(Color::rgba_i(r, g, b, 0x80));
+                    for point in &face.landmarks.points {
+                        ctx.circle(to_display((point.0, point.1).into()), 3.0);
                     }
                     ctx.fill()?;
                 }
@@ -295,8 +274,6 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
                     |v| Some(format!("{:.1} ms", v * 1000.0)),
                 )?;
 
-                self.inference_time_graph
-                    .update(inference_time.as_secs_f32());
                 self.inference_time_graph.render(
                     ctx,
                     Rect {
@@ -309,38 +286,237 @@ impl<R: RendererDevice> demo::Demo<R> for DemoDraw {
                     |_| None,
                 )?;
 
-                self.render_time_graph.render(
+                self.latency_graph.render(
                     ctx,
                     Rect {
                         xy: (430.0, 10.0).into(),
                         size: (200.0, 50.0).into(),
                     },
+                    Color::rgb_i(0x60, 0xe0, 0x60),
+                    |v| v * 1000.0 / 100.0,
+                    |v| Some(format!("{:.1} ms", v * 1000.0)),
+                    |_| None,
+                )?;
+
+                self.render_time_graph.render(
+                    ctx,
+                    Rect {
+                        xy: (640.0, 10.0).into(),
+                        size: (200.0, 50.0).into(),
+                    },
                     Color::rgb_i(0xFF, 0x64, 0x64),
                     |v| v * 1000.0 / 5.0,
                     |v| Some(format!("{:.1} ms", v * 1000.0)),
                     |_| None,
                 )?;
+
+                let staged = self
+                    .result
+                    .as_ref()
+                    .is_some_and(|analyzed| analyzed.stage_times.is_some());
+                if staged {
+                    self.detection_time_graph.render(
+                        ctx,
+                        Rect {
+                            xy: (10.0, 70.0).into(),
+                            size: (200.0, 50.0).into(),
+                        },
+                        Color::rgb_i(0x30, 0xc8, 0xff),
+                        |v| v * 1000.0 / 50.0,
+                        |v| Some(format!("{:.1} ms", v * 1000.0)),
+                        |_| None,
+                    )?;
+                    self.landmark_time_graph.render(
+                        ctx,
+                        Rect {
+                            xy: (220.0, 70.0).into(),
+                            size: (200.0, 50.0).into(),
+                        },
+                        Color::rgb_i(0xc0, 0x90, 0xff),
+                        |v| v * 1000.0 / 50.0,
+                        |v| Some(format!("{:.1} ms", v * 1000.0)),
+                        |_| None,
+                    )?;
+                }
+
+                if let Some(frame_count) = self.worker.frame_count() {
+                    ctx.text_align(Align::TOP | Align::LEFT);
+                    ctx.font_size(20.0);
+                    ctx.fill_paint(nvgx::Color::rgba_i(240, 240, 240, 192));
+                    ctx.text(
+                        (10.0, if staged { 130.0 } else { 70.0 }),
+                        &format!(
+                            "frame {}/{}{}",
+                            frame.index,
+                            frame_count,
+                            if self.worker.paused() {
+                                " (paused)"
+                            } else {
+                                ""
+                            }
+                        ),
+                    )?;
+                }
             }
         }
 
         Ok(())
     }
+
+    fn key_event(&mut self, key: KeyCode, state: ElementState) {
+        let Some(frame_count) = self.worker.frame_count() else {
+            return;
+        };
+        if state != ElementState::Pressed || frame_count == 0 {
+            return;
+        }
+        let current = self.frame.as_ref().map_or(0, |frame| frame.index);
+        let last = frame_count - 1;
+        let worker = &self.worker;
+        match key {
+            KeyCode::Space => worker.set_paused(!worker.paused()),
+            KeyCode::ArrowRight => {
+                worker.set_paused(true);
+                worker.seek(u64::min(current + 1, last));
+            }
+            KeyCode::ArrowLeft => {
+                worker.set_paused(true);
+                worker.seek(current.saturating_sub(1));
+            }
+            KeyCode::PageDown => worker.seek(u64::min(current + SEEK_STEP, last)),
+            KeyCode::PageUp => worker.seek(current.saturating_sub(SEEK_STEP)),
+            KeyCode::Home => worker.seek(0),
+            KeyCode::End => worker.seek(last),
+            _ => {}
+        }
+    }
+
+    fn exit(&mut self) -> anyhow::Result<()> {
+        if let Some(sink) = self.sink.as_mut() {
+            sink.flush()?;
+        }
+        Ok(())
+    }
 }
 
 fn main() {
     tracing_subscriber::fmt::init();
     Client::start();
+
+    let config = Config::load().unwrap_or_else(|e| exit_with(e));
+    let session_options = config.session_options().unwrap_or_else(|e| exit_with(e));
+
+    let source = config.source.open().unwrap_or_else(|e| exit_with(e));
+    let preprocess = config
+        .detection
+        .preprocess()
+        .unwrap_or_else(|e| exit_with(e));
+
+    if let Some(path) = &config.replay {
+        // draw recorded results instead of running inference
+        let mut recording = RecordingReader::open(path).unwrap_or_else(|e| exit_with(e));
+        let header = recording.header().clone();
+        let source: Box<dyn FrameSource + Send> = source.unwrap_or_else(|| {
+            let blank = BlankSource::new(
+                recording
+                    .frame_size()
+                    .unwrap_or_else(|e| exit_with(e))
+                    .unwrap_or((640, 480)),
+                recording.frame_count().unwrap_or_else(|e| exit_with(e)),
+                30.0,
+            );
+            Box::new(blank.unwrap_or_else(|e| exit_with(e)))
+        });
+        let sink = sink::open_all(&config.outputs, &header).unwrap_or_else(|e| exit_with(e));
+        run_demo(
+            Box::new(move || Ok(source)),
+            Analysis::Serial(Box::new(recording)),
+            sink,
+            preprocess,
+            &config.title,
+        );
+        return;
+    }
+
+    // the camera is opened on the thread capturing from it
+    let open_source: OpenSource = match source {
+        Some(source) => Box::new(move || Ok(source)),
+        None => Box::new(|| Ok(Box::new(CameraSource::new_default_device()))),
+    };
+    let mut pipeline = FacePipeline::new(
+        &config.models.detector,
+        &config.models.landmarker,
+        &session_options,
+    )
+    .unwrap_or_else(|e| exit_with(e));
+    let detection = &mut pipeline.detection;
+    detection.preprocess = preprocess;
+    detection.tiling = config.detection.tiling().unwrap_or_else(|e| exit_with(e));
+    detection.conf_th = config.detection.conf;
+    detection.iou_th = config.detection.iou;
+    detection.nms = config.detection.nms().unwrap_or_else(|e| exit_with(e));
+    detection.crop_margin = config.detection.crop_margin;
+    detection.align_crops = config.detection.align_crops;
+    detection.max_faces = config.detection.max_faces;
+    detection.tracker.max_lost = config.detection.max_lost;
+    pipeline.tracking = config.detection.track;
+    pipeline.redetect_interval = config.detection.redetect_interval;
+    pipeline.track_score_th = config.detection.track_score;
+    pipeline.landmark.smoother = LandmarkSmoother::new(
+        config
+            .smoothing
+            .smoothing()
+            .unwrap_or_else(|e| exit_with(e)),
+    );
+    let staged = config.staged().unwrap_or_else(|e| exit_with(e));
+    let sink = sink::open_all(&config.outputs, &pipeline.recording_header())
+        .unwrap_or_else(|e| exit_with(e));
+
+    if config.headless {
+        let source = open_source().unwrap_or_else(|e| exit_with(e));
+        headless::run(source, pipeline, sink).unwrap_or_else(|e| exit_with(e));
+        return;
+    }
+    let analysis = match staged {
+        true => {
+            let (detection, landmark) = pipeline.into_stages();
+            Analysis::Staged(Box::new(detection), Box::new(landmark))
+        }
+        false => Analysis::Serial(Box::new(pipeline)),
+    };
+    run_demo(open_source, analysis, sink, preprocess, &config.title);
+}
+
+fn exit_with(e: Error) -> ! {
+    eprintln!("{:#}", e);
+    std::process::exit(1);
+}
+
+fn run_demo(
+    open_source: OpenSource,
+    analysis: Analysis,
+    sink: Option<Box<dyn ResultSink>>,
+    preprocess: Preprocess,
+    title: &str,
+) {
+    let worker = InferenceWorker::spawn(open_source, analysis).unwrap_or_else(|e| exit_with(e));
     demo::run(
         DemoDraw {
             img_size: None,
-            camera: kamera::Camera::new_default_device(),
-            yolov5n_face: YoloV5Face::new("weights/yolov5n-face-relu.onnx").unwrap(),
-            face_land_mark: FaceLandmark::new("weights/face_landmarks_detector.onnx").unwrap(),
+            worker,
+            frame: None,
+            result: None,
+            sink,
+            preprocess,
+            written: None,
             frame_time_graph: PerfGraph::new("Frame".into()),
             inference_time_graph: PerfGraph::new("AI Inference".into()),
+            latency_graph: PerfGraph::new("Result Latency".into()),
+            detection_time_graph: PerfGraph::new("Detection".into()),
+            landmark_time_graph: PerfGraph::new("Landmarks".into()),
             render_time_graph: PerfGraph::new("GPU Render".into()),
             prev_time: Instant::now(),
         },
-        "Yolov5Face-FacelandMark(MobileNet)@Google",
+        title,
     );
 }
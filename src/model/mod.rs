@@ -0,0 +1,416 @@
+//! Loading of ONNX models into sessions checked against the signature each wrapper expects.
+//!
+//! A wrapper declares a [`ModelSpec`] and calls [`load`], which builds the session with the
+//! shared [`SessionOptions`] and fails with a [`ModelError`] instead of panicking when the file
+//! is missing or its inputs/outputs don't match.
+
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+use ndarray::Array4;
+use ort::session::Session;
+use ort::tensor::TensorElementType;
+use ort::value::{Tensor, ValueType};
+
+mod session;
+
+pub use session::SessionOptions;
+
+/// Expected size of one tensor dimension.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dim {
+    /// Anything, including dynamic (`-1`) dimensions.
+    Any,
+    /// Any fixed size, the wrapper reads it from the model.
+    Fixed,
+    Exact(i64),
+}
+
+impl Dim {
+    fn matches(&self, dim: i64) -> bool {
+        match *self {
+            Dim::Any => true,
+            Dim::Fixed => dim > 0,
+            Dim::Exact(v) => dim == v,
+        }
+    }
+}
+
+impl Display for Dim {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Dim::Any => write!(f, "?"),
+            Dim::Fixed => write!(f, "N"),
+            Dim::Exact(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+/// Expected shape of a tensor.
+#[derive(Debug, Clone, Copy)]
+pub enum Shape {
+    Dims(&'static [Dim]),
+    /// Any rank holding this many elements per batch, dynamic dimensions count as one.
+    Elements(i64),
+}
+
+impl Shape {
+    fn matches(&self, dims: &[i64]) -> bool {
+        match *self {
+            Shape::Dims(expected) => {
+                expected.len() == dims.len()
+                    && expected.iter().zip(dims).all(|(d, &v)| d.matches(v))
+            }
+            Shape::Elements(n) => dims.iter().map(|&v| v.max(1)).product::<i64>() == n,
+        }
+    }
+}
+
+impl Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shape::Dims(dims) => {
+                let dims: Vec<String> = dims.iter().map(|d| d.to_string()).collect();
+                write!(f, "[{}]", dims.join(", "))
+            }
+            Shape::Elements(n) => write!(f, "{} elements", n),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TensorSpec {
+    /// `None` accepts any name. Tensors are matched by position either way.
+    pub name: Option<&'static str>,
+    pub ty: TensorElementType,
+    pub shape: Shape,
+}
+
+impl TensorSpec {
+    pub const fn f32(shape: Shape) -> Self {
+        Self {
+            name: None,
+            ty: TensorElementType::Float32,
+            shape,
+        }
+    }
+
+    /// Requires the tensor to have this name.
+    pub const fn named(self, name: &'static str) -> Self {
+        Self {
+            name: Some(name),
+            ..self
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct ModelSpec {
+    pub name: &'static str,
+    pub inputs: &'static [TensorSpec],
+    pub outputs: &'static [TensorSpec],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TensorKind {
+    Input,
+    Output,
+}
+
+impl Display for TensorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TensorKind::Input => write!(f, "input"),
+            TensorKind::Output => write!(f, "output"),
+        }
+    }
+}
+
+/// Dimensions of a model input or output as reported by onnxruntime.
+#[derive(Debug, Clone)]
+pub struct TensorInfo {
+    pub dims: Vec<i64>,
+}
+
+/// How a model's signature differs from its [`ModelSpec`].
+#[derive(Debug)]
+pub enum Mismatch {
+    TensorCount {
+        kind: TensorKind,
+        expected: usize,
+        found: usize,
+    },
+    Name {
+        kind: TensorKind,
+        index: usize,
+        expected: &'static str,
+        found: String,
+    },
+    NotTensor {
+        kind: TensorKind,
+        name: String,
+    },
+    ElementType {
+        kind: TensorKind,
+        name: String,
+        expected: TensorElementType,
+        found: TensorElementType,
+    },
+    Shape {
+        kind: TensorKind,
+        name: String,
+        expected: Shape,
+        found: Vec<i64>,
+    },
+}
+
+impl Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Mismatch::TensorCount {
+                kind,
+                expected,
+                found,
+            } => write!(f, "expected {} {}s, found {}", expected, kind, found),
+            Mismatch::Name {
+                kind,
+                index,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} {} is named `{}`, expected `{}`",
+                kind, index, found, expected
+            ),
+            Mismatch::NotTensor { kind, name } => write!(f, "{} `{}` is not a tensor", kind, name),
+            Mismatch::ElementType {
+                kind,
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} `{}` has element type {:?}, expected {:?}",
+                kind, name, found, expected
+            ),
+            Mismatch::Shape {
+                kind,
+                name,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{} `{}` has shape {:?}, expected {}",
+                kind, name, found, expected
+            ),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum ModelError {
+    NotFound(PathBuf),
+    Load {
+        path: PathBuf,
+        source: ort::Error,
+    },
+    /// The file loaded but is not the model the wrapper expects.
+    Signature {
+        path: PathBuf,
+        model: &'static str,
+        mismatch: Mismatch,
+    },
+}
+
+impl Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ModelError::NotFound(path) => write!(f, "model file {} not found", path.display()),
+            ModelError::Load { path, source } => {
+                write!(f, "failed to load {}: {}", path.display(), source)
+            }
+            ModelError::Signature {
+                path,
+                model,
+                mismatch,
+            } => write!(
+                f,
+                "{} is not a {} model: {}",
+                path.display(),
+                model,
+                mismatch
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ModelError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ModelError::Load { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+/// A session whose inputs and outputs matched its [`ModelSpec`].
+pub struct Model {
+    pub session: Session,
+    /// Execution provider the session was bound to.
+    pub provider: &'static str,
+    pub inputs: Vec<TensorInfo>,
+    pub outputs: Vec<TensorInfo>,
+}
+
+/// Input tensor owning its buffer. Wrappers keep one per input shape, fill it in place before
+/// every run and pass it to the session by reference, so no frame allocates or copies its input.
+/// Zeroed once on creation, every element is initialised before it can be read.
+pub fn input_tensor(shape: [usize; 4]) -> ort::Result<Tensor<f32>> {
+    Tensor::from_array(Array4::<f32>::zeros(shape))
+}
+
+pub fn load<P: AsRef<Path>>(
+    spec: &ModelSpec,
+    path: P,
+    options: &SessionOptions,
+) -> Result<Model, ModelError> {
+    let path = path.as_ref();
+    let (session, provider) = options.commit_from_file(path)?;
+    let signature_error = |mismatch| ModelError::Signature {
+        path: path.to_path_buf(),
+        model: spec.name,
+        mismatch,
+    };
+
+    let inputs = session
+        .inputs
+        .iter()
+        .map(|i| (i.name.as_str(), &i.input_type));
+    let inputs = validate(TensorKind::Input, spec.inputs, inputs).map_err(signature_error)?;
+    let outputs = session
+        .outputs
+        .iter()
+        .map(|o| (o.name.as_str(), &o.output_type));
+    let outputs = validate(TensorKind::Output, spec.outputs, outputs).map_err(signature_error)?;
+
+    Ok(Model {
+        session,
+        provider,
+        inputs,
+        outputs,
+    })
+}
+
+fn validate<'a>(
+    kind: TensorKind,
+    specs: &[TensorSpec],
+    found: impl ExactSizeIterator<Item = (&'a str, &'a ValueType)>,
+) -> Result<Vec<TensorInfo>, Mismatch> {
+    if found.len() != specs.len() {
+        return Err(Mismatch::TensorCount {
+            kind,
+            expected: specs.len(),
+            found: found.len(),
+        });
+    }
+
+    found
+        .zip(specs)
+        .enumerate()
+        .map(|(index, ((name, value_type), spec))| {
+            if let Some(expected) = spec.name.filter(|n| *n != name) {
+                return Err(Mismatch::Name {
+                    kind,
+                    index,
+                    expected,
+                    found: name.to_string(),
+                });
+            }
+            let ValueType::Tensor { ty, dimensions, .. } = value_type else {
+                return Err(Mismatch::NotTensor {
+                    kind,
+                    name: name.to_string(),
+                });
+            };
+            if *ty != spec.ty {
+                return Err(Mismatch::ElementType {
+                    kind,
+                    name: name.to_string(),
+                    expected: spec.ty,
+                    found: *ty,
+                });
+            }
+            if !spec.shape.matches(dimensions) {
+                return Err(Mismatch::Shape {
+                    kind,
+                    name: name.to_string(),
+                    expected: spec.shape,
+                    found: dimensions.clone(),
+                });
+            }
+            Ok(TensorInfo {
+                dims: dimensions.clone(),
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SPECS: &[TensorSpec] = &[
+        TensorSpec::f32(Shape::Dims(&[Dim::Any, Dim::Exact(3)])).named("input"),
+        TensorSpec::f32(Shape::Elements(6)),
+    ];
+
+    fn tensor(dims: &[i64]) -> ValueType {
+        ValueType::Tensor {
+            ty: TensorElementType::Float32,
+            dimensions: dims.to_vec(),
+            dimension_symbols: vec![None; dims.len()],
+        }
+    }
+
+    #[test]
+    fn accepts_matching_tensors() {
+        let (a, b) = (tensor(&[-1, 3]), tensor(&[1, 2, 3]));
+        let found = [("input", &a), ("anything", &b)];
+        let infos = validate(TensorKind::Input, SPECS, found.into_iter()).unwrap();
+        assert_eq!(infos[0].dims, vec![-1, 3]);
+        assert_eq!(infos[1].dims, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_wrong_name() {
+        let (a, b) = (tensor(&[1, 3]), tensor(&[6]));
+        let found = [("images", &a), ("anything", &b)];
+        match validate(TensorKind::Input, SPECS, found.into_iter()) {
+            Err(Mismatch::Name {
+                kind: TensorKind::Input,
+                index: 0,
+                expected: "input",
+                found,
+            }) => assert_eq!(found, "images"),
+            other => panic!("expected a name mismatch, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_wrong_shape_and_count() {
+        let (a, b) = (tensor(&[1, 4]), tensor(&[6]));
+        let found = [("input", &a), ("anything", &b)];
+        assert!(matches!(
+            validate(TensorKind::Input, SPECS, found.into_iter()),
+            Err(Mismatch::Shape { .. })
+        ));
+        assert!(matches!(
+            validate(TensorKind::Output, SPECS, [("input", &a)].into_iter()),
+            Err(Mismatch::TensorCount {
+                expected: 2,
+                found: 1,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,197 @@
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::bail;
+use ort::execution_providers::{
+    CPUExecutionProvider, CUDAExecutionProvider, CoreMLExecutionProvider,
+    DirectMLExecutionProvider, ExecutionProvider, OpenVINOExecutionProvider, ROCmExecutionProvider,
+    TensorRTExecutionProvider, XNNPACKExecutionProvider,
+};
+use ort::session::Session;
+use ort::session::builder::{GraphOptimizationLevel, SessionBuilder};
+
+use super::ModelError;
+
+/// Execution providers that can be requested for a model session, in priority order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Provider {
+    Cpu,
+    DirectML,
+    Cuda,
+    TensorRT,
+    CoreML,
+    OpenVINO,
+    ROCm,
+    Xnnpack,
+}
+
+impl Provider {
+    fn with<T>(&self, f: impl FnOnce(&dyn ExecutionProvider) -> T) -> T {
+        match self {
+            Provider::Cpu => f(&CPUExecutionProvider::default()),
+            Provider::DirectML => f(&DirectMLExecutionProvider::default()),
+            Provider::Cuda => f(&CUDAExecutionProvider::default()),
+            Provider::TensorRT => f(&TensorRTExecutionProvider::default()),
+            Provider::CoreML => f(&CoreMLExecutionProvider::default()),
+            Provider::OpenVINO => f(&OpenVINOExecutionProvider::default()),
+            Provider::ROCm => f(&ROCmExecutionProvider::default()),
+            Provider::Xnnpack => f(&XNNPACKExecutionProvider::default()),
+        }
+    }
+
+    /// Name ONNX Runtime uses for this provider.
+    pub fn as_str(&self) -> &'static str {
+        self.with(|ep| ep.as_str())
+    }
+
+    fn register(&self, builder: &mut SessionBuilder) -> anyhow::Result<()> {
+        self.with(|ep| {
+            if !ep.supported_by_platform() {
+                bail!("not supported on this platform");
+            }
+            if !ep.is_available()? {
+                bail!("not compiled into the loaded onnxruntime");
+            }
+            ep.register(builder)?;
+            Ok(())
+        })
+    }
+}
+
+impl FromStr for Provider {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "cpu" => Provider::Cpu,
+            "directml" | "dml" => Provider::DirectML,
+            "cuda" => Provider::Cuda,
+            "tensorrt" | "trt" => Provider::TensorRT,
+            "coreml" => Provider::CoreML,
+            "openvino" => Provider::OpenVINO,
+            "rocm" => Provider::ROCm,
+            "xnnpack" => Provider::Xnnpack,
+            _ => bail!("unknown execution provider `{}`", s),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OptimizationLevel {
+    Disable,
+    Level1,
+    Level2,
+    Level3,
+}
+
+impl From<OptimizationLevel> for GraphOptimizationLevel {
+    fn from(value: OptimizationLevel) -> Self {
+        match value {
+            OptimizationLevel::Disable => GraphOptimizationLevel::Disable,
+            OptimizationLevel::Level1 => GraphOptimizationLevel::Level1,
+            OptimizationLevel::Level2 => GraphOptimizationLevel::Level2,
+            OptimizationLevel::Level3 => GraphOptimizationLevel::Level3,
+        }
+    }
+}
+
+impl FromStr for OptimizationLevel {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "disable" | "0" => OptimizationLevel::Disable,
+            "level1" | "1" => OptimizationLevel::Level1,
+            "level2" | "2" => OptimizationLevel::Level2,
+            "level3" | "3" => OptimizationLevel::Level3,
+            _ => bail!("unknown graph optimization level `{}`", s),
+        })
+    }
+}
+
+/// ONNX Runtime session settings shared by all models.
+#[derive(Debug, Clone)]
+pub struct SessionOptions {
+    pub providers: Vec<Provider>,
+    /// `None` keeps the onnxruntime default.
+    pub intra_threads: Option<usize>,
+    pub inter_threads: Option<usize>,
+    pub optimization_level: OptimizationLevel,
+    pub parallel_execution: bool,
+}
+
+impl Default for SessionOptions {
+    fn default() -> Self {
+        let providers = if cfg!(windows) {
+            vec![Provider::DirectML, Provider::Cpu]
+        } else {
+            vec![Provider::Cpu]
+        };
+        Self {
+            providers,
+            intra_threads: None,
+            inter_threads: Some(4),
+            optimization_level: OptimizationLevel::Level3,
+            parallel_execution: true,
+        }
+    }
+}
+
+impl SessionOptions {
+    /// Parses a comma separated provider list such as `cuda,cpu`.
+    pub fn parse_providers(list: &str) -> anyhow::Result<Vec<Provider>> {
+        list.split(',')
+            .filter(|s| !s.trim().is_empty())
+            .map(|s| s.trim().parse())
+            .collect()
+    }
+
+    /// Builds a session for `model`, returning it with the name of the highest priority
+    /// execution provider that was actually registered.
+    pub fn commit_from_file<P: AsRef<Path>>(
+        &self,
+        model: P,
+    ) -> Result<(Session, &'static str), ModelError> {
+        let model = model.as_ref();
+        let load_error = |source| ModelError::Load {
+            path: model.to_path_buf(),
+            source,
+        };
+        if !model.is_file() {
+            return Err(ModelError::NotFound(model.to_path_buf()));
+        }
+
+        let mut builder = Session::builder().map_err(load_error)?;
+        let mut bound = None;
+        for provider in &self.providers {
+            match provider.register(&mut builder) {
+                Ok(()) => {
+                    bound.get_or_insert(provider.as_str());
+                }
+                Err(e) => eprintln!("{}: skipping {}: {}", model.display(), provider.as_str(), e),
+            }
+        }
+
+        let session = self
+            .configure(builder)
+            .and_then(|builder| builder.commit_from_file(model))
+            .map_err(load_error)?;
+
+        // onnxruntime always falls back to the CPU provider
+        let bound = bound.unwrap_or(Provider::Cpu.as_str());
+        eprintln!("{}: bound to {}", model.display(), bound);
+        Ok((session, bound))
+    }
+
+    fn configure(&self, mut builder: SessionBuilder) -> ort::Result<SessionBuilder> {
+        if let Some(threads) = self.intra_threads {
+            builder = builder.with_intra_threads(threads)?;
+        }
+        if let Some(threads) = self.inter_threads {
+            builder = builder.with_inter_threads(threads)?;
+        }
+        builder
+            .with_parallel_execution(self.parallel_execution)?
+            .with_optimization_level(self.optimization_level.into())
+    }
+}
@@ -0,0 +1,439 @@
+use std::path::Path;
+use std::time::Duration;
+
+use fast_image_resize::IntoImageView;
+use fast_image_resize::images::ImageRef;
+use num_traits::AsPrimitive;
+use nvgx::{Point, Rect};
+use tracy_client::span;
+
+use crate::crop::{FaceCrop, roll_angle};
+use crate::face_landmark::{FaceLandmark, FaceLandmarkResult};
+use crate::model::SessionOptions;
+use crate::recording::{ModelInfo, RecordingHeader};
+use crate::smoothing::{LandmarkSmoother, Smoothing};
+use crate::source::Frame;
+use crate::tracker::{FaceTracker, Track, TrackState};
+use crate::yolov5_face::{Nms, Preprocess, Tiling, YoloResult, YoloV5Face};
+
+/// Landmarks of one face together with the crop they were computed from, in source pixels.
+pub struct FaceResult {
+    /// Identifies the same face across frames.
+    pub id: u64,
+    /// Index into [`FrameResult::faces`].
+    pub face: usize,
+    pub crop: FaceCrop,
+    pub landmarks: FaceLandmarkResult,
+}
+
+/// Everything the pipeline produced for one frame. All coordinates are in source image pixels.
+pub struct FrameResult {
+    pub index: u64,
+    pub timestamp: Duration,
+    pub size: (u32, u32),
+    pub faces: Vec<YoloResult>,
+    /// Landmarks of up to [`DetectionStage::max_faces`] faces, confirmed tracks first, then by
+    /// detection confidence.
+    pub landmarks: Vec<FaceResult>,
+    /// Tracker state after this frame, including faces that are currently lost.
+    pub tracks: Vec<Track>,
+}
+
+/// Produces a [`FrameResult`] for a frame, either by running inference or by looking it up in
+/// a recording.
+pub trait FrameAnalyzer {
+    fn analyze(&mut self, frame: &Frame) -> anyhow::Result<FrameResult>;
+}
+
+/// Faces found by [`DetectionStage`] and picked for landmarks, the input of [`LandmarkStage`].
+pub struct Detections {
+    pub faces: Vec<YoloResult>,
+    /// Index into `faces`, track id and landmark crop of the faces to compute landmarks for.
+    selected: Vec<(usize, u64, FaceCrop)>,
+    /// Tracker state after this frame.
+    tracks: Vec<Track>,
+}
+
+/// Detector, face ids and the choice of faces for landmarks, the first stage of a
+/// [`FacePipeline`].
+pub struct DetectionStage {
+    yolov5n_face: YoloV5Face,
+    /// Gives faces stable ids, the smoother keeps a face's filter state as long as its track
+    /// lives.
+    pub tracker: FaceTracker,
+    pub conf_th: f32,
+    pub iou_th: f32,
+    pub nms: Nms,
+    /// How frames are fitted into the detector input.
+    pub preprocess: Preprocess,
+    /// Also detect on overlapping tiles of large frames, for small faces in high resolution
+    /// input.
+    pub tiling: Option<Tiling>,
+    pub crop_margin: f32,
+    /// Rotate crops so the eyes are level before running the landmark model.
+    pub align_crops: bool,
+    /// Maximum number of faces landmarks are computed for.
+    pub max_faces: usize,
+}
+
+/// Landmark model and smoothing, the second stage of a [`FacePipeline`].
+pub struct LandmarkStage {
+    face_land_mark: FaceLandmark,
+    /// Temporal filter applied to the landmarks of each face.
+    pub smoother: LandmarkSmoother,
+}
+
+/// Face detection followed by landmark estimation on the most confident faces.
+pub struct FacePipeline {
+    pub detection: DetectionStage,
+    pub landmark: LandmarkStage,
+    model_names: (String, String),
+    /// Derive each frame's crops from the previous landmarks instead of running the detector.
+    pub tracking: bool,
+    /// Frames after which the detector runs again while tracking, to pick up new faces.
+    pub redetect_interval: u32,
+    /// Landmark score below which a tracked face counts as lost.
+    pub track_score_th: f32,
+    tracked: Vec<(u64, FaceCrop)>,
+    frames_since_detection: u32,
+}
+
+impl FacePipeline {
+    pub fn new<P1: AsRef<Path>, P2: AsRef<Path>>(
+        yolov5_model: P1,
+        face_land_mark_model: P2,
+        options: &SessionOptions,
+    ) -> anyhow::Result<Self> {
+        let model_name = |p: &Path| {
+            p.file_stem()
+                .map(|s| s.to_string_lossy().into_owned())
+                .unwrap_or_default()
+        };
+        let model_names = (
+            model_name(yolov5_model.as_ref()),
+            model_name(face_land_mark_model.as_ref()),
+        );
+        Ok(Self {
+            detection: DetectionStage {
+                yolov5n_face: YoloV5Face::new(yolov5_model, options)?,
+                tracker: FaceTracker::default(),
+                conf_th: 0.6,
+                iou_th: 0.5,
+                nms: Nms::Standard,
+                preprocess: Preprocess::Letterbox,
+                tiling: None,
+                crop_margin: 1.5,
+                align_crops: true,
+                max_faces: 3,
+            },
+            landmark: LandmarkStage {
+                face_land_mark: FaceLandmark::new(face_land_mark_model, options)?,
+                smoother: LandmarkSmoother::new(Smoothing::DEFAULT_ONE_EURO),
+            },
+            model_names,
+            tracking: false,
+            redetect_interval: 10,
+            track_score_th: 0.7,
+            tracked: Vec::new(),
+            frames_since_detection: 0,
+        })
+    }
+
+    /// Execution providers the detector and landmark sessions were bound to.
+    pub fn providers(&self) -> (&'static str, &'static str) {
+        (
+            self.detection.yolov5n_face.provider,
+            self.landmark.face_land_mark.provider,
+        )
+    }
+
+    pub fn recording_header(&self) -> RecordingHeader {
+        let input_shape = self.detection.yolov5n_face.input_shape;
+        RecordingHeader::new(
+            ModelInfo {
+                name: self.model_names.0.clone(),
+                input_size: (input_shape.1 as u32, input_shape.0 as u32),
+            },
+            ModelInfo {
+                name: self.model_names.1.clone(),
+                input_size: (
+                    FaceLandmark::INPUT_SIZE as u32,
+                    FaceLandmark::INPUT_SIZE as u32,
+                ),
+            },
+        )
+    }
+
+    /// Separates the stages to run them on their own threads. Tracking needs each frame's
+    /// landmarks before the next frame's crops and is dropped, [`Config::staged`] rejects it.
+    ///
+    /// [`Config::staged`]: crate::config::Config::staged
+    pub fn into_stages(self) -> (DetectionStage, LandmarkStage) {
+        (self.detection, self.landmark)
+    }
+
+    pub fn process(&mut self, frame: &Frame) -> anyhow::Result<FrameResult> {
+        let _pipeline = span!("Pipeline");
+        let src_img = frame.image_ref()?;
+        let img_size = (frame.size.0 as f32, frame.size.1 as f32);
+
+        let detect = !self.tracking
+            || self.tracked.is_empty()
+            || self.frames_since_detection >= self.redetect_interval;
+        let (faces, mut landmarks) = if detect {
+            self.frames_since_detection = 0;
+            let detections = self.detection.detect(&src_img, img_size)?;
+            let landmarks = self.landmark.landmarks(&src_img, &detections.selected)?;
+            (detections.faces, landmarks)
+        } else {
+            self.track(&src_img)?
+        };
+
+        if self.tracking {
+            // next frame's crops follow this frame's landmarks
+            self.frames_since_detection += 1;
+            self.tracked = landmarks
+                .iter()
+                .map(|face| {
+                    let face_box = face_from_landmarks(&face.landmarks);
+                    (face.id, self.detection.face_crop(&face_box, img_size))
+                })
+                .collect();
+        }
+        let tracks = self.detection.tracker.tracks();
+        self.landmark
+            .smoother
+            .apply(frame.timestamp, &mut landmarks, tracks);
+
+        Ok(FrameResult {
+            index: frame.index,
+            timestamp: frame.timestamp,
+            size: frame.size,
+            faces,
+            landmarks,
+            tracks: tracks.to_vec(),
+        })
+    }
+
+    /// Runs only the landmark model on the crops derived from the previous frame's landmarks.
+    /// The reported faces are the bounding boxes of the new landmarks.
+    fn track(&mut self, src_img: &ImageRef) -> anyhow::Result<(Vec<YoloResult>, Vec<FaceResult>)> {
+        let _track = span!("Track");
+        let tracked = std::mem::take(&mut self.tracked);
+        let crops: Vec<FaceCrop> = tracked.iter().map(|&(_, crop)| crop).collect();
+        let results = self.landmark.face_land_mark.proc_images(src_img, &crops)?;
+
+        let mut faces = Vec::with_capacity(tracked.len());
+        let mut landmarks = Vec::with_capacity(tracked.len());
+        for ((id, crop), result) in tracked.into_iter().zip(results) {
+            match result {
+                Some(result) if result.score >= self.track_score_th => {
+                    faces.push(face_from_landmarks(&result));
+                    landmarks.push(FaceResult {
+                        id,
+                        face: faces.len() - 1,
+                        crop,
+                        landmarks: result,
+                    });
+                }
+                // lost the face, find it again with the detector on the next frame
+                _ => self.frames_since_detection = self.redetect_interval,
+            }
+        }
+        let seen: Vec<(u64, Rect)> = landmarks
+            .iter()
+            .map(|face| (face.id, faces[face.face].bbox))
+            .collect();
+        self.detection.tracker.update_known(&seen);
+        Ok((faces, landmarks))
+    }
+}
+
+impl DetectionStage {
+    pub fn process(&mut self, frame: &Frame) -> anyhow::Result<Detections> {
+        let _detection = span!("Detection Stage");
+        let img_size = (frame.size.0 as f32, frame.size.1 as f32);
+        self.detect(&frame.image_ref()?, img_size)
+    }
+
+    /// Runs the detector and picks the most confident faces for landmarks.
+    fn detect(
+        &mut self,
+        src_img: &impl IntoImageView,
+        img_size: (f32, f32),
+    ) -> anyhow::Result<Detections> {
+        let faces = match self.tiling {
+            Some(tiling) => self.yolov5n_face.proc_image_tiled(
+                src_img,
+                tiling,
+                self.preprocess,
+                self.conf_th,
+                self.iou_th,
+                self.nms,
+            )?,
+            None => self.yolov5n_face.proc_image(
+                src_img,
+                self.preprocess,
+                self.conf_th,
+                self.iou_th,
+                self.nms,
+            )?,
+        };
+
+        // every face gets an id, landmarks only the established and most confident ones so the
+        // selection doesn't flip between faces of similar confidence
+        let boxes: Vec<Rect> = faces.iter().map(|face| face.bbox).collect();
+        let face_ids = self.tracker.update(&boxes);
+        let confirmed = |idx: usize| {
+            self.tracker
+                .get(face_ids[idx])
+                .is_some_and(|t| t.state == TrackState::Confirmed)
+        };
+        let mut by_conf: Vec<usize> = (0..faces.len()).collect();
+        by_conf.sort_by(|&a, &b| {
+            confirmed(b)
+                .cmp(&confirmed(a))
+                .then(faces[b].conf.total_cmp(&faces[a].conf))
+        });
+        by_conf.truncate(self.max_faces);
+
+        let selected = by_conf
+            .into_iter()
+            .map(|idx| (idx, face_ids[idx], self.face_crop(&faces[idx], img_size)))
+            .collect();
+        Ok(Detections {
+            faces,
+            selected,
+            tracks: self.tracker.tracks().to_vec(),
+        })
+    }
+
+    /// Landmark crop for a detected face, rolled to the line through its eye keypoints when
+    /// [`DetectionStage::align_crops`] is set.
+    fn face_crop(&self, face: &YoloResult, img_size: (f32, f32)) -> FaceCrop {
+        let (rect, center) =
+            mk_face_land_mark_crop_from_bbox(face.bbox, img_size, self.crop_margin);
+        let angle = match self.align_crops {
+            true => roll_angle(face.landmarks[0], face.landmarks[1]),
+            false => 0.0,
+        };
+        // rotated crops may reach past the image border, the warp fills that with black
+        let crop = FaceCrop {
+            center,
+            size: rect.size.width,
+            angle,
+        };
+        if crop.is_rotated() {
+            crop
+        } else {
+            FaceCrop::from_rect(rect)
+        }
+    }
+}
+
+impl LandmarkStage {
+    /// Computes and smooths the landmarks of the faces `detections` picked in `frame`.
+    pub fn process(
+        &mut self,
+        frame: &Frame,
+        detections: Detections,
+    ) -> anyhow::Result<FrameResult> {
+        let _landmark = span!("Landmark Stage");
+        let mut landmarks = self.landmarks(&frame.image_ref()?, &detections.selected)?;
+        self.smoother
+            .apply(frame.timestamp, &mut landmarks, &detections.tracks);
+        Ok(FrameResult {
+            index: frame.index,
+            timestamp: frame.timestamp,
+            size: frame.size,
+            faces: detections.faces,
+            landmarks,
+            tracks: detections.tracks,
+        })
+    }
+
+    fn landmarks(
+        &mut self,
+        src_img: &ImageRef,
+        selected: &[(usize, u64, FaceCrop)],
+    ) -> anyhow::Result<Vec<FaceResult>> {
+        let crops: Vec<FaceCrop> = selected.iter().map(|&(_, _, crop)| crop).collect();
+        Ok(self
+            .face_land_mark
+            .proc_images(src_img, &crops)?
+            .into_iter()
+            .zip(selected)
+            .filter_map(|(result, &(face, id, crop))| {
+                result.map(|landmarks| FaceResult {
+                    id,
+                    face,
+                    crop,
+                    landmarks,
+                })
+            })
+            .collect())
+    }
+}
+
+/// Detection-like result spanning the landmarks, with the eye centres, nose tip and mouth
+/// corners as keypoints.
+fn face_from_landmarks(landmarks: &FaceLandmarkResult) -> YoloResult {
+    let (mut min, mut max) = ((f32::MAX, f32::MAX), (f32::MIN, f32::MIN));
+    for &(x, y, _) in &landmarks.points {
+        min = (min.0.min(x), min.1.min(y));
+        max = (max.0.max(x), max.1.max(y));
+    }
+    YoloResult {
+        conf: landmarks.score,
+        bbox: Rect {
+            xy: min.into(),
+            size: (max.0 - min.0, max.1 - min.1).into(),
+        },
+        landmarks: FaceLandmark::KEYPOINTS.map(|i| {
+            let p = landmarks.points[i];
+            Point::new(p.0, p.1)
+        }),
+    }
+}
+
+impl FrameAnalyzer for FacePipeline {
+    fn analyze(&mut self, frame: &Frame) -> anyhow::Result<FrameResult> {
+        self.process(frame)
+    }
+}
+
+/// Square crop around `bbox` enlarged by `margin` and clamped to the image.
+#[inline]
+pub fn mk_face_land_mark_crop_from_bbox<N: AsPrimitive<f32>>(
+    bbox: Rect,
+    img_size: (N, N),
+    margin: f32,
+) -> (Rect, Point) {
+    let img_size: (f32, f32) = (img_size.0.as_(), img_size.1.as_());
+    let max_size = f32::max(bbox.size.width, bbox.size.height) * margin;
+    let max_size = f32::min(max_size, f32::min(img_size.0, img_size.1));
+    let half_max_size = max_size / 2.0;
+    let center = (
+        bbox.xy.x + bbox.size.width / 2.0,
+        bbox.xy.y + bbox.size.height / 2.0,
+    );
+
+    let mut left = f32::max(0.0, center.0 - half_max_size);
+    let right = left + max_size;
+    if right > img_size.0 {
+        left = img_size.0 - max_size;
+    }
+    let mut top = f32::max(0.0, center.1 - half_max_size);
+    let bottom = top + max_size;
+    if bottom > img_size.1 {
+        top = img_size.1 - max_size;
+    }
+
+    (
+        Rect {
+            xy: (left, top).into(),
+            size: (max_size, max_size).into(),
+        },
+        center.into(),
+    )
+}
@@ -0,0 +1,269 @@
+//! Compact binary recording of pipeline results.
+//!
+//! Layout, all integers and floats little endian:
+//!
+//! ```text
+//! header:  magic "FLMREC\0\0" | version: u16 | detector: model | landmarker: model
+//! model:   name_len: u16 | name: utf8 | input_width: u32 | input_height: u32
+//! record:  payload_len: u32 | payload
+//! payload: frame: u64 | timestamp_us: u64 | width: u32 | height: u32
+//!          | faces: u16 | face*faces | landmarks: u16 | landmark*landmarks
+//! face:    conf: f32 | bbox: [f32; 4] | keypoints: [f32; 10]
+//! landmark: face: u16 | id: u32 | crop: [f32; 4] | crop_angle: f32 | score: f32 | tongue: f32
+//!          | points: u32 | [f32; 3] * points
+//! ```
+//!
+//! Coordinates are source image pixels, rectangles are `x, y, width, height`. A crop is the
+//! square before rotating it by `crop_angle` radians around its centre. Readers skip any
+//! bytes past the known fields of a record, so records can grow in later versions.
+//!
+//! Version 1 recordings have no landmark `id`, faces are numbered in record order instead.
+//! Versions before 3 have no `crop_angle`, their crops are axis aligned.
+
+use std::io::{self, Read, Write};
+
+mod reader;
+mod writer;
+
+pub use reader::RecordingReader;
+pub use writer::RecordingWriter;
+
+pub const MAGIC: &[u8; 8] = b"FLMREC\0\0";
+pub const VERSION: u16 = 3;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModelInfo {
+    pub name: String,
+    pub input_size: (u32, u32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordingHeader {
+    pub version: u16,
+    pub detector: ModelInfo,
+    pub landmarker: ModelInfo,
+}
+
+impl RecordingHeader {
+    pub fn new(detector: ModelInfo, landmarker: ModelInfo) -> Self {
+        Self {
+            version: VERSION,
+            detector,
+            landmarker,
+        }
+    }
+
+    fn write<W: Write>(&self, w: &mut W) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_all(&self.version.to_le_bytes())?;
+        for model in [&self.detector, &self.landmarker] {
+            w.write_all(&(model.name.len() as u16).to_le_bytes())?;
+            w.write_all(model.name.as_bytes())?;
+            w.write_all(&model.input_size.0.to_le_bytes())?;
+            w.write_all(&model.input_size.1.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(r: &mut R) -> anyhow::Result<Self> {
+        let mut magic = [0u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            anyhow::bail!("not a landmark recording");
+        }
+        let version = read_u16(r)?;
+        if version > VERSION {
+            anyhow::bail!("unsupported recording version {}", version);
+        }
+        let mut read_model = || -> anyhow::Result<ModelInfo> {
+            let mut name = vec![0u8; read_u16(r)? as usize];
+            r.read_exact(&mut name)?;
+            Ok(ModelInfo {
+                name: String::from_utf8(name)?,
+                input_size: (read_u32(r)?, read_u32(r)?),
+            })
+        };
+        let detector = read_model()?;
+        let landmarker = read_model()?;
+        Ok(Self {
+            version,
+            detector,
+            landmarker,
+        })
+    }
+}
+
+#[inline]
+fn read_u16<R: Read>(r: &mut R) -> io::Result<u16> {
+    let mut b = [0u8; 2];
+    r.read_exact(&mut b)?;
+    Ok(u16::from_le_bytes(b))
+}
+
+#[inline]
+fn read_u32<R: Read>(r: &mut R) -> io::Result<u32> {
+    let mut b = [0u8; 4];
+    r.read_exact(&mut b)?;
+    Ok(u32::from_le_bytes(b))
+}
+
+#[inline]
+fn read_u64<R: Read>(r: &mut R) -> io::Result<u64> {
+    let mut b = [0u8; 8];
+    r.read_exact(&mut b)?;
+    Ok(u64::from_le_bytes(b))
+}
+
+#[inline]
+fn read_f32<R: Read>(r: &mut R) -> io::Result<f32> {
+    Ok(f32::from_bits(read_u32(r)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+    use std::time::Duration;
+
+    use nvgx::{Point, Rect};
+
+    use super::*;
+    use crate::crop::FaceCrop;
+    use crate::face_landmark::FaceLandmarkResult;
+    use crate::pipeline::{FaceResult, FrameResult};
+    use crate::sink::ResultSink;
+    use crate::yolov5_face::YoloResult;
+
+    fn model(name: &str, size: u32) -> ModelInfo {
+        ModelInfo {
+            name: name.into(),
+            input_size: (size, size),
+        }
+    }
+
+    fn landmark(id: u64, face: usize, x: f32) -> FaceResult {
+        FaceResult {
+            id,
+            face,
+            crop: FaceCrop {
+                center: Point::new(x, 20.0),
+                size: 16.0,
+                angle: 0.25,
+            },
+            landmarks: FaceLandmarkResult {
+                points: vec![(x, 1.0, 2.0), (x + 1.0, 3.0, 4.0)],
+                score: 0.75,
+                tongue: 0.5,
+            },
+        }
+    }
+
+    fn frame(index: u64) -> FrameResult {
+        let x = index as f32 * 10.0;
+        FrameResult {
+            index,
+            timestamp: Duration::from_millis(index * 33),
+            size: (64, 48),
+            faces: vec![YoloResult {
+                conf: 0.9,
+                bbox: Rect {
+                    xy: (x, 2.0).into(),
+                    size: (8.0, 8.0).into(),
+                },
+                landmarks: [Point::new(x, 1.0); 5],
+            }],
+            landmarks: vec![landmark(7, 0, x), landmark(3, 0, x + 5.0)],
+            tracks: Vec::new(),
+        }
+    }
+
+    fn record(version: u16, indices: &[u64]) -> Vec<u8> {
+        let mut header = RecordingHeader::new(model("detector", 640), model("landmarker", 256));
+        header.version = version;
+        let mut data = Vec::new();
+        let mut writer = RecordingWriter::new(&mut data, &header).unwrap();
+        for &index in indices {
+            writer.write(&frame(index)).unwrap();
+        }
+        data
+    }
+
+    fn rect(r: Rect) -> [f32; 4] {
+        [r.xy.x, r.xy.y, r.size.width, r.size.height]
+    }
+
+    fn assert_frame(version: u16, read: &FrameResult) {
+        let expected = frame(read.index);
+        assert_eq!(read.timestamp, expected.timestamp);
+        assert_eq!(read.size, expected.size);
+        assert_eq!(read.faces.len(), 1);
+        assert_eq!(read.faces[0].conf, expected.faces[0].conf);
+        assert_eq!(rect(read.faces[0].bbox), rect(expected.faces[0].bbox));
+        assert_eq!(
+            read.faces[0].landmarks.map(|p| (p.x, p.y)),
+            expected.faces[0].landmarks.map(|p| (p.x, p.y))
+        );
+        assert_eq!(read.landmarks.len(), expected.landmarks.len());
+        for (i, (read, expected)) in read.landmarks.iter().zip(&expected.landmarks).enumerate() {
+            // version 1 numbers faces in record order, versions before 3 have axis aligned crops
+            let id = if version == 1 { i as u64 } else { expected.id };
+            let angle = if version < 3 {
+                0.0
+            } else {
+                expected.crop.angle
+            };
+            assert_eq!(read.id, id);
+            assert_eq!(read.face, expected.face);
+            assert_eq!(rect(read.crop.rect()), rect(expected.crop.rect()));
+            assert_eq!(read.crop.angle, angle);
+            assert_eq!(read.landmarks.points, expected.landmarks.points);
+            assert_eq!(read.landmarks.score, expected.landmarks.score);
+            assert_eq!(read.landmarks.tongue, expected.landmarks.tongue);
+        }
+    }
+
+    #[test]
+    fn round_trips_every_version() {
+        let indices = [0, 2, 5];
+        for version in 1..=VERSION {
+            let mut reader = RecordingReader::new(Cursor::new(record(version, &indices))).unwrap();
+            assert_eq!(reader.header().version, version);
+            assert_eq!(reader.header().detector, model("detector", 640));
+            assert_eq!(reader.header().landmarker, model("landmarker", 256));
+
+            let frames = reader.by_ref().collect::<anyhow::Result<Vec<_>>>().unwrap();
+            assert_eq!(frames.iter().map(|f| f.index).collect::<Vec<_>>(), indices);
+            for frame in &frames {
+                assert_frame(version, frame);
+            }
+        }
+    }
+
+    #[test]
+    fn seeks_every_version() {
+        for version in 1..=VERSION {
+            let mut reader =
+                RecordingReader::new(Cursor::new(record(version, &[0, 2, 5]))).unwrap();
+            // frames dropped while recording still count
+            assert_eq!(reader.frame_count().unwrap(), 6);
+            assert_eq!(reader.frame_size().unwrap(), Some((64, 48)));
+
+            for index in [5, 0, 2] {
+                assert!(reader.seek_frame(index).unwrap());
+                let read = reader.read_frame().unwrap().unwrap();
+                assert_eq!(read.index, index);
+                assert_frame(version, &read);
+            }
+            assert!(!reader.seek_frame(3).unwrap());
+            assert!(!reader.seek_frame(6).unwrap());
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        for version in [0, VERSION + 1] {
+            let mut header = RecordingHeader::new(model("detector", 640), model("landmarker", 256));
+            header.version = version;
+            assert!(RecordingWriter::new(Vec::new(), &header).is_err());
+        }
+    }
+}
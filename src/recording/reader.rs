@@ -0,0 +1,224 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::time::Duration;
+
+use nvgx::{Point, Rect};
+
+use super::{RecordingHeader, read_f32, read_u16, read_u32, read_u64};
+use crate::crop::FaceCrop;
+use crate::face_landmark::FaceLandmarkResult;
+use crate::pipeline::{FaceResult, FrameAnalyzer, FrameResult};
+use crate::source::Frame;
+use crate::yolov5_face::YoloResult;
+
+/// Reads frame results back from a binary recording.
+///
+/// Iterating yields records in file order. For seekable readers [`RecordingReader::seek_frame`]
+/// jumps to a frame index, and the reader can stand in for the inference pipeline through
+/// [`FrameAnalyzer`].
+pub struct RecordingReader<R: Read> {
+    reader: R,
+    header: RecordingHeader,
+    payload: Vec<u8>,
+    index: Option<RecordingIndex>,
+}
+
+struct RecordingIndex {
+    offsets: BTreeMap<u64, u64>,
+    frame_size: Option<(u32, u32)>,
+}
+
+impl RecordingReader<BufReader<File>> {
+    pub fn open<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::new(BufReader::new(File::open(path)?))
+    }
+}
+
+impl<R: Read> RecordingReader<R> {
+    pub fn new(mut reader: R) -> anyhow::Result<Self> {
+        let header = RecordingHeader::read(&mut reader)?;
+        Ok(Self {
+            reader,
+            header,
+            payload: Vec::new(),
+            index: None,
+        })
+    }
+
+    pub fn header(&self) -> &RecordingHeader {
+        &self.header
+    }
+
+    /// Reads the next record, `Ok(None)` at the end of the recording.
+    pub fn read_frame(&mut self) -> anyhow::Result<Option<FrameResult>> {
+        let mut len = [0u8; 4];
+        match self.reader.read_exact(&mut len) {
+            Ok(()) => {}
+            Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+            Err(e) => return Err(e.into()),
+        }
+        self.payload.resize(u32::from_le_bytes(len) as usize, 0);
+        self.reader.read_exact(&mut self.payload)?;
+        Ok(Some(Self::parse(
+            &mut self.payload.as_slice(),
+            self.header.version,
+        )?))
+    }
+
+    fn parse(r: &mut &[u8], version: u16) -> anyhow::Result<FrameResult> {
+        let read_rect = |r: &mut &[u8]| -> anyhow::Result<Rect> {
+            Ok(Rect {
+                xy: (read_f32(r)?, read_f32(r)?).into(),
+                size: (read_f32(r)?, read_f32(r)?).into(),
+            })
+        };
+
+        let index = read_u64(r)?;
+        let timestamp = Duration::from_micros(read_u64(r)?);
+        let size = (read_u32(r)?, read_u32(r)?);
+
+        let mut faces = Vec::new();
+        for _ in 0..read_u16(r)? {
+            let conf = read_f32(r)?;
+            let bbox = read_rect(r)?;
+            let mut landmarks = [Point::default(); 5];
+            for p in landmarks.iter_mut() {
+                *p = (read_f32(r)?, read_f32(r)?).into();
+            }
+            faces.push(YoloResult {
+                conf,
+                bbox,
+                landmarks,
+            });
+        }
+
+        let mut landmarks = Vec::new();
+        for i in 0..read_u16(r)? {
+            let face = read_u16(r)? as usize;
+            let id = match version {
+                1 => i as u64,
+                _ => read_u32(r)? as u64,
+            };
+            let mut crop = FaceCrop::from_rect(read_rect(r)?);
+            if version >= 3 {
+                crop.angle = read_f32(r)?;
+            }
+            let score = read_f32(r)?;
+            let tongue = read_f32(r)?;
+            let points = (0..read_u32(r)?)
+                .map(|_| Ok((read_f32(r)?, read_f32(r)?, read_f32(r)?)))
+                .collect::<std::io::Result<Vec<_>>>()?;
+            landmarks.push(FaceResult {
+                id,
+                face,
+                crop,
+                landmarks: FaceLandmarkResult {
+                    points,
+                    score,
+                    tongue,
+                },
+            });
+        }
+
+        Ok(FrameResult {
+            index,
+            timestamp,
+            size,
+            faces,
+            landmarks,
+            tracks: Vec::new(),
+        })
+    }
+}
+
+impl<R: Read + Seek> RecordingReader<R> {
+    fn index(&mut self) -> anyhow::Result<&RecordingIndex> {
+        if self.index.is_none() {
+            let resume = self.reader.stream_position()?;
+            self.reader.seek(SeekFrom::Start(self.header_len()))?;
+
+            let mut offsets = BTreeMap::new();
+            let mut frame_size = None;
+            loop {
+                let offset = self.reader.stream_position()?;
+                let len = match read_u32(&mut self.reader) {
+                    Ok(len) => len as i64,
+                    Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                    Err(e) => return Err(e.into()),
+                };
+                // frame: u64 | timestamp_us: u64 | width: u32 | height: u32
+                let frame = read_u64(&mut self.reader)?;
+                read_u64(&mut self.reader)?;
+                let size = (read_u32(&mut self.reader)?, read_u32(&mut self.reader)?);
+                frame_size.get_or_insert(size);
+                self.reader.seek(SeekFrom::Current(len - 24))?;
+                offsets.insert(frame, offset);
+            }
+
+            self.reader.seek(SeekFrom::Start(resume))?;
+            self.index = Some(RecordingIndex {
+                offsets,
+                frame_size,
+            });
+        }
+        Ok(self.index.as_ref().unwrap())
+    }
+
+    fn header_len(&self) -> u64 {
+        let model_len = |name: &str| 2 + name.len() as u64 + 8;
+        8 + 2 + model_len(&self.header.detector.name) + model_len(&self.header.landmarker.name)
+    }
+
+    /// Number of source frames the recording spans, the last recorded index plus one. Frames
+    /// dropped while recording count too.
+    pub fn frame_count(&mut self) -> anyhow::Result<u64> {
+        Ok(self
+            .index()?
+            .offsets
+            .last_key_value()
+            .map_or(0, |(&index, _)| index + 1))
+    }
+
+    /// Source image size of the first recorded frame.
+    pub fn frame_size(&mut self) -> anyhow::Result<Option<(u32, u32)>> {
+        Ok(self.index()?.frame_size)
+    }
+
+    /// Positions the reader on the record of frame `index`, returns `false` if it was never
+    /// recorded.
+    pub fn seek_frame(&mut self, index: u64) -> anyhow::Result<bool> {
+        let Some(&offset) = self.index()?.offsets.get(&index) else {
+            return Ok(false);
+        };
+        self.reader.seek(SeekFrom::Start(offset))?;
+        Ok(true)
+    }
+}
+
+impl<R: Read> Iterator for RecordingReader<R> {
+    type Item = anyhow::Result<FrameResult>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read_frame().transpose()
+    }
+}
+
+impl<R: Read + Seek> FrameAnalyzer for RecordingReader<R> {
+    fn analyze(&mut self, frame: &Frame) -> anyhow::Result<FrameResult> {
+        if self.seek_frame(frame.index)?
+            && let Some(result) = self.read_frame()?
+        {
+            return Ok(result);
+        }
+        Ok(FrameResult {
+            index: frame.index,
+            timestamp: frame.timestamp,
+            size: frame.size,
+            faces: Vec::new(),
+            landmarks: Vec::new(),
+            tracks: Vec::new(),
+        })
+    }
+}
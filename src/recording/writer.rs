@@ -0,0 +1,93 @@
+use std::io::Write;
+
+use nvgx::Rect;
+
+use super::{RecordingHeader, VERSION};
+use crate::pipeline::FrameResult;
+use crate::sink::ResultSink;
+
+/// [`ResultSink`] writing the binary recording format described in [`super`]. Records leave
+/// out the fields the version of the header doesn't have yet.
+pub struct RecordingWriter<W: Write> {
+    writer: W,
+    version: u16,
+    payload: Vec<u8>,
+}
+
+impl<W: Write> RecordingWriter<W> {
+    pub fn new(mut writer: W, header: &RecordingHeader) -> anyhow::Result<Self> {
+        if header.version == 0 || header.version > VERSION {
+            anyhow::bail!("unsupported recording version {}", header.version);
+        }
+        header.write(&mut writer)?;
+        Ok(Self {
+            writer,
+            version: header.version,
+            payload: Vec::new(),
+        })
+    }
+
+    #[inline]
+    fn put_f32s(&mut self, values: &[f32]) {
+        for v in values {
+            self.payload.extend_from_slice(&v.to_le_bytes());
+        }
+    }
+
+    #[inline]
+    fn put_rect(&mut self, r: Rect) {
+        self.put_f32s(&[r.xy.x, r.xy.y, r.size.width, r.size.height]);
+    }
+}
+
+impl<W: Write> ResultSink for RecordingWriter<W> {
+    fn write(&mut self, result: &FrameResult) -> anyhow::Result<()> {
+        self.payload.clear();
+        self.payload.extend_from_slice(&result.index.to_le_bytes());
+        self.payload
+            .extend_from_slice(&(result.timestamp.as_micros() as u64).to_le_bytes());
+        self.payload.extend_from_slice(&result.size.0.to_le_bytes());
+        self.payload.extend_from_slice(&result.size.1.to_le_bytes());
+
+        self.payload
+            .extend_from_slice(&(result.faces.len() as u16).to_le_bytes());
+        for face in &result.faces {
+            self.put_f32s(&[face.conf]);
+            self.put_rect(face.bbox);
+            for p in face.landmarks {
+                self.put_f32s(&[p.x, p.y]);
+            }
+        }
+
+        self.payload
+            .extend_from_slice(&(result.landmarks.len() as u16).to_le_bytes());
+        for face in &result.landmarks {
+            self.payload
+                .extend_from_slice(&(face.face as u16).to_le_bytes());
+            if self.version >= 2 {
+                self.payload
+                    .extend_from_slice(&(face.id as u32).to_le_bytes());
+            }
+            self.put_rect(face.crop.rect());
+            if self.version >= 3 {
+                self.put_f32s(&[face.crop.angle]);
+            }
+            self.put_f32s(&[face.landmarks.score, face.landmarks.tongue]);
+            self.payload
+                .extend_from_slice(&(face.landmarks.points.len() as u32).to_le_bytes());
+            for p in &face.landmarks.points {
+                self.put_f32s(&[p.0, p.1, p.2]);
+            }
+        }
+
+        self.writer
+            .write_all(&(self.payload.len() as u32).to_le_bytes())?;
+        self.writer.write_all(&self.payload)?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,118 @@
+use std::io::Write;
+
+use nvgx::{Point, Rect};
+use serde::Serialize;
+
+use super::ResultSink;
+use crate::pipeline::FrameResult;
+use crate::tracker::TrackState;
+
+#[derive(Serialize)]
+struct FrameRecord<'a> {
+    frame: u64,
+    timestamp: f64,
+    width: u32,
+    height: u32,
+    faces: Vec<FaceRecord<'a>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tracks: Vec<TrackRecord>,
+}
+
+#[derive(Serialize)]
+struct FaceRecord<'a> {
+    bbox: [f32; 4],
+    conf: f32,
+    keypoints: [[f32; 2]; 5],
+    landmarks: Option<LandmarksRecord<'a>>,
+}
+
+#[derive(Serialize)]
+struct LandmarksRecord<'a> {
+    id: u64,
+    crop: [f32; 4],
+    /// Rotation of the crop around its centre in radians.
+    crop_angle: f32,
+    score: f32,
+    tongue: f32,
+    points: &'a [(f32, f32, f32)],
+}
+
+#[derive(Serialize)]
+struct TrackRecord {
+    id: u64,
+    state: TrackState,
+    age: u32,
+    bbox: [f32; 4],
+}
+
+#[inline]
+fn rect(r: Rect) -> [f32; 4] {
+    [r.xy.x, r.xy.y, r.size.width, r.size.height]
+}
+
+#[inline]
+fn point(p: Point) -> [f32; 2] {
+    [p.x, p.y]
+}
+
+/// Writes one JSON object per frame. Coordinates are source image pixels, bboxes and crops are
+/// `[x, y, width, height]`.
+pub struct JsonLinesSink<W: Write> {
+    writer: W,
+}
+
+impl<W: Write> JsonLinesSink<W> {
+    pub fn new(writer: W) -> Self {
+        Self { writer }
+    }
+}
+
+impl<W: Write> ResultSink for JsonLinesSink<W> {
+    fn write(&mut self, result: &FrameResult) -> anyhow::Result<()> {
+        let faces = result
+            .faces
+            .iter()
+            .enumerate()
+            .map(|(idx, face)| FaceRecord {
+                bbox: rect(face.bbox),
+                conf: face.conf,
+                keypoints: face.landmarks.map(point),
+                landmarks: result.landmarks.iter().find(|l| l.face == idx).map(|l| {
+                    LandmarksRecord {
+                        id: l.id,
+                        crop: rect(l.crop.rect()),
+                        crop_angle: l.crop.angle,
+                        score: l.landmarks.score,
+                        tongue: l.landmarks.tongue,
+                        points: &l.landmarks.points,
+                    }
+                }),
+            })
+            .collect();
+        let record = FrameRecord {
+            frame: result.index,
+            timestamp: result.timestamp.as_secs_f64(),
+            width: result.size.0,
+            height: result.size.1,
+            faces,
+            tracks: result
+                .tracks
+                .iter()
+                .map(|t| TrackRecord {
+                    id: t.id,
+                    state: t.state,
+                    age: t.age,
+                    bbox: rect(t.bbox),
+                })
+                .collect(),
+        };
+        serde_json::to_writer(&mut self.writer, &record)?;
+        self.writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,74 @@
+use std::fs::File;
+use std::io::{BufWriter, stdout};
+use std::path::Path;
+
+use crate::pipeline::FrameResult;
+use crate::recording::{RecordingHeader, RecordingWriter};
+
+mod jsonl;
+
+pub use jsonl::JsonLinesSink;
+
+/// Consumer of per-frame pipeline results.
+pub trait ResultSink {
+    fn write(&mut self, result: &FrameResult) -> anyhow::Result<()>;
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// File extension selecting the binary recording format, see [`crate::recording`].
+pub const RECORDING_EXTENSION: &str = "lmrec";
+
+/// Opens a sink for `path`: `-` writes JSON Lines to stdout, `*.lmrec` a binary recording and
+/// anything else a JSON Lines file.
+pub fn open(path: &str, header: &RecordingHeader) -> anyhow::Result<Box<dyn ResultSink>> {
+    if path == "-" {
+        return Ok(Box::new(JsonLinesSink::new(stdout().lock())));
+    }
+    let file = BufWriter::new(File::create(path)?);
+    let is_recording = Path::new(path)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case(RECORDING_EXTENSION));
+    if is_recording {
+        Ok(Box::new(RecordingWriter::new(file, header)?))
+    } else {
+        Ok(Box::new(JsonLinesSink::new(file)))
+    }
+}
+
+/// Opens a sink for each of `paths`, see [`open`], combining them when there are several.
+pub fn open_all(
+    paths: &[String],
+    header: &RecordingHeader,
+) -> anyhow::Result<Option<Box<dyn ResultSink>>> {
+    let mut sinks = paths
+        .iter()
+        .map(|path| open(path, header))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    Ok(match sinks.len() {
+        0 => None,
+        1 => sinks.pop(),
+        _ => Some(Box::new(MultiSink(sinks))),
+    })
+}
+
+/// Writes every result to all of its sinks.
+pub struct MultiSink(pub Vec<Box<dyn ResultSink>>);
+
+impl ResultSink for MultiSink {
+    fn write(&mut self, result: &FrameResult) -> anyhow::Result<()> {
+        for sink in &mut self.0 {
+            sink.write(result)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        for sink in &mut self.0 {
+            sink.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,287 @@
+//! Temporal smoothing of face landmarks.
+//!
+//! Each coordinate of each landmark is filtered on its own, per face id. A face's filters are
+//! reset once its track is lost, so a face that reappears starts unfiltered instead of from
+//! stale positions and speeds.
+
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::bail;
+
+use crate::pipeline::FaceResult;
+use crate::tracker::{Track, TrackState};
+
+/// Filter applied to landmark coordinates.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    None,
+    /// One Euro filter: a low pass whose cutoff rises with speed, smoothing slow movement
+    /// strongly while following fast movement with little lag.
+    OneEuro {
+        /// Cutoff frequency in Hz at rest, lower values smooth more.
+        min_cutoff: f32,
+        /// Cutoff increase per pixel/s of speed, higher values lag less.
+        beta: f32,
+        /// Cutoff frequency in Hz used to smooth the speed estimate.
+        d_cutoff: f32,
+    },
+    /// Exponential moving average, `alpha` is the weight of the newest frame.
+    Ema {
+        alpha: f32,
+    },
+}
+
+impl Smoothing {
+    pub const DEFAULT_ONE_EURO: Smoothing = Smoothing::OneEuro {
+        min_cutoff: 1.0,
+        beta: 0.01,
+        d_cutoff: 1.0,
+    };
+    pub const DEFAULT_EMA: Smoothing = Smoothing::Ema { alpha: 0.5 };
+}
+
+impl FromStr for Smoothing {
+    type Err = anyhow::Error;
+
+    /// Parses the method name, parameters take their defaults.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "none" | "off" => Smoothing::None,
+            "one-euro" | "one_euro" | "oneeuro" => Smoothing::DEFAULT_ONE_EURO,
+            "ema" => Smoothing::DEFAULT_EMA,
+            _ => bail!("unknown smoothing method `{}`", s),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct OneEuroFilter {
+    x: f32,
+    dx: f32,
+}
+
+impl OneEuroFilter {
+    #[inline]
+    fn alpha(cutoff: f32, dt: f32) -> f32 {
+        let tau = 1.0 / (2.0 * PI * cutoff);
+        1.0 / (1.0 + tau / dt)
+    }
+
+    #[inline]
+    fn filter(&mut self, x: f32, dt: f32, min_cutoff: f32, beta: f32, d_cutoff: f32) -> f32 {
+        let dx = (x - self.x) / dt;
+        self.dx += (dx - self.dx) * Self::alpha(d_cutoff, dt);
+        let cutoff = min_cutoff + beta * self.dx.abs();
+        self.x += (x - self.x) * Self::alpha(cutoff, dt);
+        self.x
+    }
+}
+
+struct FaceFilter {
+    timestamp: Duration,
+    points: Vec<[OneEuroFilter; 3]>,
+}
+
+impl FaceFilter {
+    fn new(timestamp: Duration, points: &[(f32, f32, f32)]) -> Self {
+        let state = |x| OneEuroFilter { x, dx: 0.0 };
+        Self {
+            timestamp,
+            points: points
+                .iter()
+                .map(|p| [state(p.0), state(p.1), state(p.2)])
+                .collect(),
+        }
+    }
+}
+
+/// Keeps the filter state of every face between frames.
+pub struct LandmarkSmoother {
+    pub smoothing: Smoothing,
+    faces: HashMap<u64, FaceFilter>,
+}
+
+impl LandmarkSmoother {
+    /// Frame interval assumed when timestamps don't advance.
+    const DEFAULT_DT: f32 = 1.0 / 30.0;
+
+    pub fn new(smoothing: Smoothing) -> Self {
+        Self {
+            smoothing,
+            faces: HashMap::new(),
+        }
+    }
+
+    /// Smooths the landmarks of `faces` in place and forgets faces whose track is lost or ended.
+    pub fn apply(&mut self, timestamp: Duration, faces: &mut [FaceResult], tracks: &[Track]) {
+        self.faces.retain(|&id, _| {
+            tracks
+                .iter()
+                .any(|track| track.id == id && track.state != TrackState::Lost)
+        });
+        if self.smoothing == Smoothing::None {
+            self.faces.clear();
+            return;
+        }
+
+        for face in faces.iter_mut() {
+            let points = &mut face.landmarks.points;
+            let Some(filter) = self
+                .faces
+                .get_mut(&face.id)
+                .filter(|f| f.points.len() == points.len())
+            else {
+                // first frame of a track passes through unchanged
+                self.faces
+                    .insert(face.id, FaceFilter::new(timestamp, points));
+                continue;
+            };
+
+            let dt = timestamp.saturating_sub(filter.timestamp).as_secs_f32();
+            let dt = if dt > 0.0 { dt } else { Self::DEFAULT_DT };
+            filter.timestamp = timestamp;
+            for (p, state) in points.iter_mut().zip(filter.points.iter_mut()) {
+                let [x, y, z] = state;
+                *p = match self.smoothing {
+                    Smoothing::OneEuro {
+                        min_cutoff,
+                        beta,
+                        d_cutoff,
+                    } => (
+                        x.filter(p.0, dt, min_cutoff, beta, d_cutoff),
+                        y.filter(p.1, dt, min_cutoff, beta, d_cutoff),
+                        z.filter(p.2, dt, min_cutoff, beta, d_cutoff),
+                    ),
+                    Smoothing::Ema { alpha } => {
+                        x.x += (p.0 - x.x) * alpha;
+                        y.x += (p.1 - y.x) * alpha;
+                        z.x += (p.2 - z.x) * alpha;
+                        (x.x, y.x, z.x)
+                    }
+                    Smoothing::None => *p,
+                };
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use nvgx::Rect;
+
+    use super::*;
+    use crate::crop::FaceCrop;
+    use crate::face_landmark::FaceLandmarkResult;
+
+    const DT: Duration = Duration::from_millis(40);
+
+    fn face(x: f32) -> FaceResult {
+        FaceResult {
+            id: 0,
+            face: 0,
+            crop: FaceCrop::from_rect(Rect {
+                xy: (0.0, 0.0).into(),
+                size: (10.0, 10.0).into(),
+            }),
+            landmarks: FaceLandmarkResult {
+                points: vec![(x, 2.0 * x, 3.0)],
+                score: 1.0,
+                tongue: 0.0,
+            },
+        }
+    }
+
+    fn track(state: TrackState) -> Track {
+        Track {
+            id: 0,
+            bbox: Rect {
+                xy: (0.0, 0.0).into(),
+                size: (10.0, 10.0).into(),
+            },
+            state,
+            age: 1,
+            hits: 1,
+            misses: 0,
+        }
+    }
+
+    /// Runs `xs` through the smoother at a fixed frame interval and returns the filtered xs.
+    fn run(smoother: &mut LandmarkSmoother, xs: &[f32]) -> Vec<f32> {
+        let tracks = [track(TrackState::Confirmed)];
+        xs.iter()
+            .enumerate()
+            .map(|(i, &x)| {
+                let mut faces = [face(x)];
+                smoother.apply(DT * i as u32, &mut faces, &tracks);
+                faces[0].landmarks.points[0].0
+            })
+            .collect()
+    }
+
+    fn one_euro(beta: f32) -> LandmarkSmoother {
+        LandmarkSmoother::new(Smoothing::OneEuro {
+            min_cutoff: 1.0,
+            beta,
+            d_cutoff: 1.0,
+        })
+    }
+
+    #[test]
+    fn constant_signal_passes_through() {
+        for smoothing in [
+            Smoothing::DEFAULT_ONE_EURO,
+            Smoothing::DEFAULT_EMA,
+            Smoothing::None,
+        ] {
+            let out = run(&mut LandmarkSmoother::new(smoothing), &[5.0; 10]);
+            assert!(out.iter().all(|&x| x == 5.0), "{:?}: {:?}", smoothing, out);
+        }
+    }
+
+    #[test]
+    fn one_euro_attenuates_steps_less_with_higher_beta() {
+        let step = [0.0, 0.0, 100.0, 100.0];
+        let slow = run(&mut one_euro(0.0), &step);
+        let fast = run(&mut one_euro(1.0), &step);
+        for out in [&slow, &fast] {
+            assert_eq!(out[..2], [0.0, 0.0]);
+            assert!(
+                out[2] > 0.0 && out[2] < out[3] && out[3] < 100.0,
+                "{:?}",
+                out
+            );
+        }
+        assert!(
+            fast[2] > slow[2] && fast[3] > slow[3],
+            "{:?} {:?}",
+            slow,
+            fast
+        );
+    }
+
+    #[test]
+    fn ema_weights_newest_frame_by_alpha() {
+        let mut smoother = LandmarkSmoother::new(Smoothing::Ema { alpha: 0.25 });
+        assert_eq!(run(&mut smoother, &[0.0, 100.0, 100.0]), [0.0, 25.0, 43.75]);
+    }
+
+    #[test]
+    fn resets_after_lost_track() {
+        let mut smoother = LandmarkSmoother::new(Smoothing::Ema { alpha: 0.5 });
+        run(&mut smoother, &[0.0, 0.0]);
+        smoother.apply(DT * 2, &mut [], &[track(TrackState::Lost)]);
+
+        let mut faces = [face(100.0)];
+        smoother.apply(DT * 3, &mut faces, &[track(TrackState::Confirmed)]);
+        assert_eq!(faces[0].landmarks.points[0], (100.0, 200.0, 3.0));
+
+        // a track that stays live keeps filtering
+        smoother.apply(DT * 4, &mut [face(0.0)], &[track(TrackState::Confirmed)]);
+        let mut faces = [face(0.0)];
+        smoother.apply(DT * 5, &mut faces, &[track(TrackState::Confirmed)]);
+        assert_eq!(faces[0].landmarks.points[0].0, 25.0);
+    }
+}
@@ -0,0 +1,59 @@
+use std::time::Duration;
+
+use anyhow::bail;
+
+use super::{Frame, FrameSource, frame_interval};
+
+/// Black frames of a fixed size, used to replay recordings when the original footage is not
+/// at hand.
+pub struct BlankSource {
+    size: (u32, u32),
+    frame_count: u64,
+    pos: u64,
+    frame_interval: Duration,
+    buffer: Vec<u8>,
+}
+
+impl BlankSource {
+    pub fn new(size: (u32, u32), frame_count: u64, frame_rate: f32) -> anyhow::Result<Self> {
+        let mut buffer = vec![0u8; size.0 as usize * size.1 as usize * 4];
+        for px in buffer.chunks_exact_mut(4) {
+            px[3] = 0xff;
+        }
+        Ok(Self {
+            size,
+            frame_count,
+            pos: 0,
+            frame_interval: frame_interval(frame_rate)?,
+            buffer,
+        })
+    }
+}
+
+impl FrameSource for BlankSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame<'_>>> {
+        if self.pos >= self.frame_count {
+            return Ok(None);
+        }
+        let index = self.pos;
+        self.pos += 1;
+        Ok(Some(Frame {
+            index,
+            timestamp: self.frame_interval * index as u32,
+            size: self.size,
+            data: &self.buffer,
+        }))
+    }
+
+    fn frame_count(&self) -> Option<u64> {
+        Some(self.frame_count)
+    }
+
+    fn seek(&mut self, index: u64) -> anyhow::Result<()> {
+        if index >= self.frame_count {
+            bail!("frame {} is out of range (0..{})", index, self.frame_count);
+        }
+        self.pos = index;
+        Ok(())
+    }
+}
@@ -0,0 +1,69 @@
+use std::time::Instant;
+
+use super::{Frame, FrameSource};
+
+pub struct CameraSource {
+    camera: kamera::Camera,
+    // `kamera::FrameData` only lends its bytes for its own lifetime, so `next_frame` copies the
+    // frame into a buffer owned by the source. `with_next_frame` lends it directly.
+    buffer: Vec<u8>,
+    size: (u32, u32),
+    start_time: Instant,
+    index: u64,
+}
+
+impl CameraSource {
+    pub fn new_default_device() -> Self {
+        Self {
+            camera: kamera::Camera::new_default_device(),
+            buffer: Vec::new(),
+            size: (0, 0),
+            start_time: Instant::now(),
+            index: 0,
+        }
+    }
+}
+
+impl FrameSource for CameraSource {
+    fn start(&mut self) -> anyhow::Result<()> {
+        self.camera.start();
+        self.start_time = Instant::now();
+        Ok(())
+    }
+
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame<'_>>> {
+        let Some(frame) = self.camera.wait_for_frame() else {
+            return Ok(None);
+        };
+        self.size = frame.size_u32();
+        self.buffer.clear();
+        self.buffer.extend_from_slice(frame.data().data_u8());
+
+        let index = self.index;
+        self.index += 1;
+        Ok(Some(Frame {
+            index,
+            timestamp: self.start_time.elapsed(),
+            size: self.size,
+            data: &self.buffer,
+        }))
+    }
+
+    fn with_next_frame(
+        &mut self,
+        f: &mut dyn FnMut(&Frame) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        let Some(frame) = self.camera.wait_for_frame() else {
+            return Ok(());
+        };
+        let data = frame.data();
+        let index = self.index;
+        self.index += 1;
+        f(&Frame {
+            index,
+            timestamp: self.start_time.elapsed(),
+            size: frame.size_u32(),
+            data: data.data_u8(),
+        })
+    }
+}
@@ -0,0 +1,111 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+
+use super::{Frame, FrameSource, frame_interval};
+
+const IMAGE_EXTENSIONS: [&str; 3] = ["png", "jpg", "jpeg"];
+
+/// Reads PNG/JPEG files from a single path, a glob pattern or a directory (in sorted order)
+/// and hands them out as BGRA frames.
+pub struct ImageSource {
+    paths: Vec<PathBuf>,
+    pos: usize,
+    frame_interval: Duration,
+    buffer: Vec<u8>,
+    size: (u32, u32),
+}
+
+impl ImageSource {
+    pub const DEFAULT_FRAME_RATE: f32 = 30.0;
+
+    pub fn open(spec: &str) -> anyhow::Result<Self> {
+        let path = Path::new(spec);
+        let paths = if spec.contains(['*', '?', '[']) {
+            let mut paths = glob::glob(spec)?
+                .filter_map(Result::ok)
+                .filter(|p| Self::is_image(p))
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        } else if path.is_dir() {
+            let mut paths = std::fs::read_dir(path)?
+                .filter_map(|e| e.ok().map(|e| e.path()))
+                .filter(|p| p.is_file() && Self::is_image(p))
+                .collect::<Vec<_>>();
+            paths.sort();
+            paths
+        } else if path.is_file() {
+            vec![path.to_path_buf()]
+        } else {
+            bail!("image source `{}` does not exist", spec);
+        };
+
+        if paths.is_empty() {
+            bail!("no png/jpeg images found for `{}`", spec);
+        }
+
+        Ok(Self {
+            paths,
+            pos: 0,
+            frame_interval: Duration::from_secs_f32(1.0 / Self::DEFAULT_FRAME_RATE),
+            buffer: Vec::new(),
+            size: (0, 0),
+        })
+    }
+
+    pub fn with_frame_rate(mut self, fps: f32) -> anyhow::Result<Self> {
+        self.frame_interval = frame_interval(fps)?;
+        Ok(self)
+    }
+
+    fn is_image(path: &Path) -> bool {
+        path.extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_ascii_lowercase().as_str()))
+    }
+
+    fn load(&mut self, path: &Path) -> anyhow::Result<()> {
+        let img = ::image::open(path)
+            .map_err(|e| anyhow!("failed to read {}: {}", path.display(), e))?
+            .into_rgba8();
+        self.size = img.dimensions();
+        self.buffer = img.into_raw();
+        // RGBA -> BGRA, the layout the camera delivers
+        for px in self.buffer.chunks_exact_mut(4) {
+            px.swap(0, 2);
+        }
+        Ok(())
+    }
+}
+
+impl FrameSource for ImageSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame<'_>>> {
+        let Some(path) = self.paths.get(self.pos).cloned() else {
+            return Ok(None);
+        };
+        self.load(&path)?;
+
+        let index = self.pos as u64;
+        self.pos += 1;
+        Ok(Some(Frame {
+            index,
+            timestamp: self.frame_interval * index as u32,
+            size: self.size,
+            data: &self.buffer,
+        }))
+    }
+
+    fn frame_count(&self) -> Option<u64> {
+        Some(self.paths.len() as u64)
+    }
+
+    fn seek(&mut self, index: u64) -> anyhow::Result<()> {
+        if index as usize >= self.paths.len() {
+            bail!("frame {} is out of range (0..{})", index, self.paths.len());
+        }
+        self.pos = index as usize;
+        Ok(())
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::bail;
+use fast_image_resize::{PixelType, images::ImageRef};
+
+mod blank;
+mod camera;
+mod image;
+mod video;
+
+pub use blank::BlankSource;
+pub use camera::CameraSource;
+pub use image::ImageSource;
+pub use video::VideoSource;
+
+/// A single BGRA frame borrowed from a [`FrameSource`].
+pub struct Frame<'a> {
+    pub index: u64,
+    pub timestamp: Duration,
+    pub size: (u32, u32),
+    pub data: &'a [u8],
+}
+
+impl<'a> Frame<'a> {
+    pub fn image_ref(&self) -> anyhow::Result<ImageRef<'a>> {
+        Ok(ImageRef::new(
+            self.size.0,
+            self.size.1,
+            self.data,
+            PixelType::U8x4,
+        )?)
+    }
+}
+
+pub trait FrameSource {
+    fn start(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    /// Returns `Ok(None)` when no frame is available, e.g. the camera dropped one or a file
+    /// source reached its end.
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame<'_>>>;
+
+    /// Passes the next frame to `f`, does nothing when [`FrameSource::next_frame`] would return
+    /// `None`. Sources that only borrow their frames, like cameras, hand them over without
+    /// copying them into a buffer of their own first.
+    fn with_next_frame(
+        &mut self,
+        f: &mut dyn FnMut(&Frame) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        match self.next_frame()? {
+            Some(frame) => f(&frame),
+            None => Ok(()),
+        }
+    }
+
+    /// Number of frames for file backed sources, `None` for live ones.
+    fn frame_count(&self) -> Option<u64> {
+        None
+    }
+
+    /// Positions the source so that the next call to [`FrameSource::next_frame`] returns the
+    /// frame with the given index.
+    fn seek(&mut self, _index: u64) -> anyhow::Result<()> {
+        bail!("source is not seekable")
+    }
+}
+
+/// Opens a file backed source, picking the decoder from the file extension. `frame_rate`
+/// applies to images, y4m files carry their own.
+pub fn open(spec: &str, frame_rate: Option<f32>) -> anyhow::Result<Box<dyn FrameSource + Send>> {
+    let is_y4m = Path::new(spec)
+        .extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("y4m"));
+    if is_y4m {
+        Ok(Box::new(VideoSource::open_y4m(spec)?))
+    } else {
+        let images = ImageSource::open(spec)?;
+        match frame_rate {
+            Some(fps) => Ok(Box::new(images.with_frame_rate(fps)?)),
+            None => Ok(Box::new(images)),
+        }
+    }
+}
+
+/// Time between frames at `frame_rate`, which has to be finite and positive.
+fn frame_interval(frame_rate: f32) -> anyhow::Result<Duration> {
+    if !(frame_rate.is_finite() && frame_rate > 0.0) {
+        bail!(
+            "invalid frame rate {}, expected a positive number",
+            frame_rate
+        );
+    }
+    Ok(Duration::try_from_secs_f32(1.0 / frame_rate)?)
+}
@@ -0,0 +1,410 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, bail};
+
+use super::{Frame, FrameSource, frame_interval};
+
+/// Pixel layout of an uncompressed raw video dump.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RawFormat {
+    Bgra,
+    Nv12,
+}
+
+impl FromStr for RawFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "bgra" => RawFormat::Bgra,
+            "nv12" => RawFormat::Nv12,
+            _ => bail!("unknown raw pixel format `{}`", s),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PixelFormat {
+    Bgra,
+    Nv12,
+    /// Planar 8-bit YUV with the given horizontal/vertical chroma subsampling shifts.
+    Planar(u32, u32),
+    Mono,
+}
+
+impl PixelFormat {
+    fn frame_len(&self, size: (u32, u32)) -> usize {
+        let (w, h) = (size.0 as usize, size.1 as usize);
+        match *self {
+            PixelFormat::Bgra => w * h * 4,
+            PixelFormat::Nv12 => w * h + w.div_ceil(2) * h.div_ceil(2) * 2,
+            PixelFormat::Planar(sx, sy) => {
+                let cw = (w + (1 << sx) - 1) >> sx;
+                let ch = (h + (1 << sy) - 1) >> sy;
+                w * h + cw * ch * 2
+            }
+            PixelFormat::Mono => w * h,
+        }
+    }
+}
+
+/// Uncompressed video file source: YUV4MPEG2 (`.y4m`) or raw BGRA/NV12 dumps.
+///
+/// Frame offsets are indexed when the file is opened, so seeking is frame accurate.
+pub struct VideoSource {
+    file: BufReader<File>,
+    format: PixelFormat,
+    full_range: bool,
+    size: (u32, u32),
+    offsets: Vec<u64>,
+    pos: usize,
+    frame_interval: Duration,
+    raw: Vec<u8>,
+    buffer: Vec<u8>,
+}
+
+impl VideoSource {
+    pub fn open_y4m<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let mut file = BufReader::new(File::open(path)?);
+        let file_len = file.get_ref().metadata()?.len();
+
+        let mut header = String::new();
+        file.read_line(&mut header)?;
+        let mut params = header.trim_end().split(' ');
+        if params.next() != Some("YUV4MPEG2") {
+            bail!("{} is not a YUV4MPEG2 file", path.display());
+        }
+
+        let (mut width, mut height) = (0, 0);
+        let mut frame_rate = 30.0;
+        let mut format = PixelFormat::Planar(1, 1);
+        let mut full_range = false;
+        for param in params.filter(|p| !p.is_empty()) {
+            let mut chars = param.chars();
+            let tag = chars.next();
+            let value = chars.as_str();
+            match tag {
+                Some('W') => width = value.parse()?,
+                Some('H') => height = value.parse()?,
+                Some('F') => {
+                    let (n, d) = value
+                        .split_once(':')
+                        .ok_or_else(|| anyhow!("invalid y4m frame rate `{}`", value))?;
+                    frame_rate = n.parse::<f32>()? / d.parse::<f32>()?;
+                }
+                Some('C') => {
+                    format = match value {
+                        "420" | "420jpeg" | "420paldv" | "420mpeg2" => PixelFormat::Planar(1, 1),
+                        "422" => PixelFormat::Planar(1, 0),
+                        "444" => PixelFormat::Planar(0, 0),
+                        "mono" => PixelFormat::Mono,
+                        _ => bail!("unsupported y4m colorspace `{}`", value),
+                    }
+                }
+                Some('X') => full_range |= value == "COLORRANGE=FULL",
+                _ => {}
+            }
+        }
+        if width == 0 || height == 0 {
+            bail!("y4m header is missing the frame size");
+        }
+
+        let frame_len = format.frame_len((width, height)) as u64;
+        let mut offsets = Vec::new();
+        let mut offset = header.len() as u64;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let n = file.read_until(b'\n', &mut line)? as u64;
+            if n == 0 {
+                break;
+            }
+            if !line.starts_with(b"FRAME") {
+                bail!("corrupt y4m frame header at byte {}", offset);
+            }
+            let data_offset = offset + n;
+            if data_offset + frame_len > file_len {
+                // truncated trailing frame
+                break;
+            }
+            offsets.push(data_offset);
+            file.seek_relative(frame_len as i64)?;
+            offset = data_offset + frame_len;
+        }
+
+        Self::new(
+            file,
+            format,
+            full_range,
+            (width, height),
+            offsets,
+            frame_rate,
+        )
+    }
+
+    pub fn open_raw<P: AsRef<Path>>(
+        path: P,
+        format: RawFormat,
+        size: (u32, u32),
+        frame_rate: f32,
+    ) -> anyhow::Result<Self> {
+        if size.0 == 0 || size.1 == 0 {
+            bail!("raw video frame size {}x{} is empty", size.0, size.1);
+        }
+        let file = BufReader::new(File::open(path)?);
+        let format = match format {
+            RawFormat::Bgra => PixelFormat::Bgra,
+            RawFormat::Nv12 => PixelFormat::Nv12,
+        };
+        let frame_len = format.frame_len(size) as u64;
+        let frames = file.get_ref().metadata()?.len() / frame_len;
+        let offsets = (0..frames).map(|i| i * frame_len).collect();
+        Self::new(file, format, false, size, offsets, frame_rate)
+    }
+
+    fn new(
+        file: BufReader<File>,
+        format: PixelFormat,
+        full_range: bool,
+        size: (u32, u32),
+        offsets: Vec<u64>,
+        frame_rate: f32,
+    ) -> anyhow::Result<Self> {
+        if offsets.is_empty() {
+            bail!("video file contains no complete frame");
+        }
+        Ok(Self {
+            file,
+            format,
+            full_range,
+            size,
+            offsets,
+            pos: 0,
+            frame_interval: frame_interval(frame_rate)?,
+            raw: vec![0; format.frame_len(size)],
+            buffer: vec![0; size.0 as usize * size.1 as usize * 4],
+        })
+    }
+
+    fn convert(&mut self) {
+        let (w, h) = (self.size.0 as usize, self.size.1 as usize);
+        let (y_plane, chroma) = self.raw.split_at(w * h);
+        match self.format {
+            PixelFormat::Bgra => self.buffer.copy_from_slice(&self.raw),
+            PixelFormat::Mono => {
+                for (dst, &y) in self.buffer.chunks_exact_mut(4).zip(y_plane) {
+                    let (r, g, b) = yuv_to_rgb(y, 128, 128, self.full_range);
+                    dst.copy_from_slice(&[b, g, r, 0xff]);
+                }
+            }
+            PixelFormat::Nv12 => {
+                let cw = w.div_ceil(2);
+                for (row, dst_row) in self.buffer.chunks_exact_mut(w * 4).enumerate() {
+                    let uv_row = &chroma[(row / 2) * cw * 2..];
+                    for (col, dst) in dst_row.chunks_exact_mut(4).enumerate() {
+                        let y = y_plane[row * w + col];
+                        let (u, v) = (uv_row[(col / 2) * 2], uv_row[(col / 2) * 2 + 1]);
+                        let (r, g, b) = yuv_to_rgb(y, u, v, self.full_range);
+                        dst.copy_from_slice(&[b, g, r, 0xff]);
+                    }
+                }
+            }
+            PixelFormat::Planar(sx, sy) => {
+                let cw = (w + (1 << sx) - 1) >> sx;
+                let ch = (h + (1 << sy) - 1) >> sy;
+                let (u_plane, v_plane) = chroma.split_at(cw * ch);
+                for (row, dst_row) in self.buffer.chunks_exact_mut(w * 4).enumerate() {
+                    let c_row = (row >> sy) * cw;
+                    for (col, dst) in dst_row.chunks_exact_mut(4).enumerate() {
+                        let c = c_row + (col >> sx);
+                        let y = y_plane[row * w + col];
+                        let (r, g, b) = yuv_to_rgb(y, u_plane[c], v_plane[c], self.full_range);
+                        dst.copy_from_slice(&[b, g, r, 0xff]);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// BT.601 YUV to RGB, limited (studio) range unless `full_range` is set.
+#[inline]
+fn yuv_to_rgb(y: u8, u: u8, v: u8, full_range: bool) -> (u8, u8, u8) {
+    let (c, d, e) = (y as i32, u as i32 - 128, v as i32 - 128);
+    let (r, g, b) = if full_range {
+        let c = c << 8;
+        (c + 359 * e, c - 88 * d - 183 * e, c + 454 * d)
+    } else {
+        let c = 298 * (c - 16);
+        (c + 409 * e, c - 100 * d - 208 * e, c + 516 * d)
+    };
+    let clip = |x: i32| ((x + 128) >> 8).clamp(0, 255) as u8;
+    (clip(r), clip(g), clip(b))
+}
+
+impl FrameSource for VideoSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame<'_>>> {
+        let Some(&offset) = self.offsets.get(self.pos) else {
+            return Ok(None);
+        };
+        self.file.seek(SeekFrom::Start(offset))?;
+        self.file.read_exact(&mut self.raw)?;
+        self.convert();
+
+        let index = self.pos as u64;
+        self.pos += 1;
+        Ok(Some(Frame {
+            index,
+            timestamp: self.frame_interval * index as u32,
+            size: self.size,
+            data: &self.buffer,
+        }))
+    }
+
+    fn frame_count(&self) -> Option<u64> {
+        Some(self.offsets.len() as u64)
+    }
+
+    fn seek(&mut self, index: u64) -> anyhow::Result<()> {
+        if index as usize >= self.offsets.len() {
+            bail!(
+                "frame {} is out of range (0..{})",
+                index,
+                self.offsets.len()
+            );
+        }
+        self.pos = index as usize;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Deletes the file when dropped.
+    struct TempFile(PathBuf);
+
+    impl TempFile {
+        fn new(name: &str, data: &[u8]) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("nvgx-test-{}-{}", std::process::id(), name));
+            std::fs::write(&path, data).unwrap();
+            Self(path)
+        }
+    }
+
+    impl Drop for TempFile {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_file(&self.0);
+        }
+    }
+
+    /// 4x2 4:2:0 y4m whose frame `i` has luma `16 + 64 * i` and neutral chroma, followed by
+    /// a truncated frame.
+    fn y4m(header: &str, frames: u8) -> Vec<u8> {
+        let mut data = format!("YUV4MPEG2 W4 H2 {}\n", header).into_bytes();
+        for i in 0..frames {
+            data.extend_from_slice(b"FRAME\n");
+            data.extend_from_slice(&[16 + 64 * i; 8]);
+            data.extend_from_slice(&[128; 4]);
+        }
+        data.extend_from_slice(b"FRAME\n");
+        data.extend_from_slice(&[0; 5]);
+        data
+    }
+
+    fn pixel(frame: &Frame) -> [u8; 4] {
+        frame.data[..4].try_into().unwrap()
+    }
+
+    #[test]
+    fn yuv_to_rgb_limited_and_full_range() {
+        assert_eq!(yuv_to_rgb(16, 128, 128, false), (0, 0, 0));
+        assert_eq!(yuv_to_rgb(235, 128, 128, false), (255, 255, 255));
+        assert_eq!(yuv_to_rgb(81, 90, 240, false), (255, 0, 0));
+        assert_eq!(yuv_to_rgb(0, 128, 128, true), (0, 0, 0));
+        assert_eq!(yuv_to_rgb(255, 128, 128, true), (255, 255, 255));
+        assert_eq!(yuv_to_rgb(76, 85, 255, true), (254, 0, 0));
+    }
+
+    #[test]
+    fn reads_y4m_frames_and_skips_truncated_frame() {
+        let file = TempFile::new("frames.y4m", &y4m("F4:1 Ip A1:1 C420jpeg", 3));
+        let mut source = VideoSource::open_y4m(&file.0).unwrap();
+        assert_eq!(source.frame_count(), Some(3));
+        for i in 0..3u8 {
+            let frame = source.next_frame().unwrap().unwrap();
+            assert_eq!(frame.index, i as u64);
+            assert_eq!(frame.timestamp, Duration::from_millis(250 * i as u64));
+            assert_eq!(frame.size, (4, 2));
+            let (r, g, b) = yuv_to_rgb(16 + 64 * i, 128, 128, false);
+            assert_eq!(pixel(&frame), [b, g, r, 0xff]);
+        }
+        assert!(source.next_frame().unwrap().is_none());
+    }
+
+    #[test]
+    fn seeks_back_and_forward() {
+        let file = TempFile::new("seek.y4m", &y4m("F30:1", 3));
+        let mut source = VideoSource::open_y4m(&file.0).unwrap();
+        for index in [2, 0, 1, 2] {
+            source.seek(index).unwrap();
+            let frame = source.next_frame().unwrap().unwrap();
+            assert_eq!(frame.index, index);
+            let y = 16 + 64 * index as u8;
+            assert_eq!(frame.data[0], yuv_to_rgb(y, 128, 128, false).2);
+        }
+        assert!(source.next_frame().unwrap().is_none());
+        assert!(source.seek(3).is_err());
+    }
+
+    #[test]
+    fn converts_full_range_planar() {
+        let mut data = b"YUV4MPEG2 W2 H2 C444 XCOLORRANGE=FULL\nFRAME\n".to_vec();
+        data.extend_from_slice(&[76, 0, 255, 128]);
+        data.extend_from_slice(&[85, 128, 128, 128]);
+        data.extend_from_slice(&[255, 128, 128, 128]);
+        let file = TempFile::new("full.y4m", &data);
+        let mut source = VideoSource::open_y4m(&file.0).unwrap();
+        let frame = source.next_frame().unwrap().unwrap();
+        assert_eq!(
+            frame.data,
+            [
+                0, 0, 254, 0xff, 0, 0, 0, 0xff, 255, 255, 255, 0xff, 128, 128, 128, 0xff
+            ]
+        );
+    }
+
+    #[test]
+    fn converts_limited_range_nv12() {
+        // 2x2 luma, one interleaved UV pair shared by all four pixels
+        let file = TempFile::new("frames.nv12", &[81, 81, 81, 81, 90, 240, 16, 16]);
+        let mut source = VideoSource::open_raw(&file.0, RawFormat::Nv12, (2, 2), 30.0).unwrap();
+        // the trailing two bytes don't make a frame
+        assert_eq!(source.frame_count(), Some(1));
+        let frame = source.next_frame().unwrap().unwrap();
+        assert_eq!(frame.data, [0, 0, 255, 0xff].repeat(4));
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let file = TempFile::new("utf8.y4m", &y4m("F25:1 \u{e4}x", 1));
+        assert!(VideoSource::open_y4m(&file.0).is_ok());
+        for (name, rate) in [("zero.y4m", "F0:1"), ("inf.y4m", "F1:0")] {
+            let file = TempFile::new(name, &y4m(rate, 1));
+            assert!(VideoSource::open_y4m(&file.0).is_err());
+        }
+        let file = TempFile::new("rate.nv12", &[16; 6]);
+        for fps in [0.0, -1.0, f32::NAN, f32::INFINITY] {
+            assert!(VideoSource::open_raw(&file.0, RawFormat::Nv12, (2, 2), fps).is_err());
+        }
+    }
+}
@@ -0,0 +1,360 @@
+//! Multi-face tracker giving detections stable ids across frames.
+//!
+//! Detections are associated with the live tracks by minimum-cost matching (Hungarian
+//! algorithm). The cost prefers overlap and falls back to the distance between box centres for
+//! fast moving faces. Tracks that are not seen for [`FaceTracker::max_lost`] frames are dropped.
+
+use nvgx::Rect;
+use serde::Serialize;
+
+use crate::utils::rect_iou;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TrackState {
+    /// Seen in fewer than [`FaceTracker::min_hits`] frames, may be a spurious detection.
+    Tentative,
+    /// Seen in the latest frame and at least [`FaceTracker::min_hits`] frames overall.
+    Confirmed,
+    /// Missing from the latest frame, kept until it times out.
+    Lost,
+}
+
+/// One tracked face.
+#[derive(Debug, Clone, Copy)]
+pub struct Track {
+    pub id: u64,
+    /// Last known bounding box in source pixels.
+    pub bbox: Rect,
+    pub state: TrackState,
+    /// Frames since the track was created.
+    pub age: u32,
+    /// Frames the face was seen in.
+    pub hits: u32,
+    /// Consecutive frames the face was missing.
+    pub misses: u32,
+}
+
+impl Track {
+    fn center(&self) -> (f32, f32) {
+        center(self.bbox)
+    }
+}
+
+pub struct FaceTracker {
+    tracks: Vec<Track>,
+    next_id: u64,
+    /// Minimum IoU for a box to continue a track by overlap.
+    pub min_iou: f32,
+    /// Maximum distance between box centres, relative to the box size, for a box without
+    /// enough overlap to still continue a track.
+    pub max_distance: f32,
+    /// Frames a track has to be seen in before it is confirmed.
+    pub min_hits: u32,
+    /// Frames a track may be missing before it is dropped.
+    pub max_lost: u32,
+}
+
+impl Default for FaceTracker {
+    fn default() -> Self {
+        Self {
+            tracks: Vec::new(),
+            next_id: 0,
+            min_iou: 0.3,
+            max_distance: 0.5,
+            min_hits: 3,
+            max_lost: 30,
+        }
+    }
+}
+
+impl FaceTracker {
+    /// Live tracks, including lost ones that haven't timed out.
+    pub fn tracks(&self) -> &[Track] {
+        &self.tracks
+    }
+
+    pub fn get(&self, id: u64) -> Option<&Track> {
+        self.tracks.iter().find(|t| t.id == id)
+    }
+
+    /// Associates this frame's detections with the tracks and returns the id of every box.
+    /// Boxes that match no track start a new one.
+    pub fn update(&mut self, boxes: &[Rect]) -> Vec<u64> {
+        let cost: Vec<Vec<f32>> = boxes
+            .iter()
+            .map(|&bbox| self.tracks.iter().map(|t| self.cost(bbox, t)).collect())
+            .collect();
+        let assignment = hungarian(&cost, self.tracks.len());
+
+        let mut seen = vec![false; self.tracks.len()];
+        let ids = boxes
+            .iter()
+            .zip(assignment)
+            .enumerate()
+            .map(|(i, (&bbox, track))| match track {
+                Some(t) if cost[i][t].is_finite() => {
+                    seen[t] = true;
+                    self.tracks[t].bbox = bbox;
+                    self.tracks[t].id
+                }
+                _ => {
+                    self.next_id += 1;
+                    self.tracks.push(Track {
+                        id: self.next_id - 1,
+                        bbox,
+                        state: TrackState::Tentative,
+                        age: 0,
+                        hits: 0,
+                        misses: 0,
+                    });
+                    seen.push(true);
+                    self.next_id - 1
+                }
+            })
+            .collect();
+        self.advance(&seen);
+        ids
+    }
+
+    /// Updates tracks whose ids are already known, e.g. from following landmarks. Tracks not
+    /// in `faces` count as missing.
+    pub fn update_known(&mut self, faces: &[(u64, Rect)]) {
+        let seen: Vec<bool> = self
+            .tracks
+            .iter_mut()
+            .map(|track| match faces.iter().find(|f| f.0 == track.id) {
+                Some(&(_, bbox)) => {
+                    track.bbox = bbox;
+                    true
+                }
+                None => false,
+            })
+            .collect();
+        self.advance(&seen);
+    }
+
+    /// Ages all tracks by a frame and drops the ones that timed out.
+    fn advance(&mut self, seen: &[bool]) {
+        for (track, &seen) in self.tracks.iter_mut().zip(seen) {
+            track.age += 1;
+            if seen {
+                track.hits += 1;
+                track.misses = 0;
+                track.state = match track.hits >= self.min_hits {
+                    true => TrackState::Confirmed,
+                    false => TrackState::Tentative,
+                };
+            } else {
+                track.misses += 1;
+                track.state = TrackState::Lost;
+            }
+        }
+        let max_lost = self.max_lost;
+        self.tracks.retain(|t| t.misses <= max_lost);
+    }
+
+    /// Matching cost of `bbox` for `track`: `1 - IoU` for overlapping boxes, `1 +` relative
+    /// centre distance for nearby ones, infinite otherwise.
+    fn cost(&self, bbox: Rect, track: &Track) -> f32 {
+        let iou = rect_iou(bbox, track.bbox);
+        if iou >= self.min_iou {
+            return 1.0 - iou;
+        }
+        let (a, b) = (center(bbox), track.center());
+        let size =
+            (bbox.size.width + bbox.size.height + track.bbox.size.width + track.bbox.size.height)
+                / 4.0;
+        let distance = f32::hypot(a.0 - b.0, a.1 - b.1) / size.max(f32::EPSILON);
+        match distance <= self.max_distance {
+            true => 1.0 + distance,
+            false => f32::INFINITY,
+        }
+    }
+}
+
+#[inline]
+fn center(r: Rect) -> (f32, f32) {
+    (r.xy.x + r.size.width / 2.0, r.xy.y + r.size.height / 2.0)
+}
+
+/// Minimum-cost assignment of `cost.len()` rows to `cols` columns. Returns the column of every
+/// row, `None` for rows left over when there are more rows than columns. Infinite costs are
+/// allowed, callers have to reject such pairs themselves.
+fn hungarian(cost: &[Vec<f32>], cols: usize) -> Vec<Option<usize>> {
+    let rows = cost.len();
+    let n = rows.max(cols);
+    if n == 0 {
+        return Vec::new();
+    }
+    // square matrix with forbidden pairs replaced by a cost larger than any valid assignment
+    let forbidden = 1.0
+        + cost
+            .iter()
+            .flatten()
+            .filter(|c| c.is_finite())
+            .map(|c| c.abs() as f64)
+            .sum::<f64>();
+    let c = |i: usize, j: usize| -> f64 {
+        match (i < rows && j < cols).then(|| cost[i][j]) {
+            Some(c) if c.is_finite() => c as f64,
+            Some(_) => forbidden,
+            None => 0.0,
+        }
+    };
+
+    // shortest augmenting path with potentials, 1-based with 0 as the virtual start column
+    let mut u = vec![0.0f64; n + 1];
+    let mut v = vec![0.0f64; n + 1];
+    let mut row_of = vec![0usize; n + 1];
+    let mut way = vec![0usize; n + 1];
+    for i in 1..=n {
+        row_of[0] = i;
+        let mut j0 = 0;
+        let mut min_v = vec![f64::INFINITY; n + 1];
+        let mut used = vec![false; n + 1];
+        loop {
+            used[j0] = true;
+            let i0 = row_of[j0];
+            let mut delta = f64::INFINITY;
+            let mut j1 = 0;
+            for j in 1..=n {
+                if used[j] {
+                    continue;
+                }
+                let reduced = c(i0 - 1, j - 1) - u[i0] - v[j];
+                if reduced < min_v[j] {
+                    min_v[j] = reduced;
+                    way[j] = j0;
+                }
+                if min_v[j] < delta {
+                    delta = min_v[j];
+                    j1 = j;
+                }
+            }
+            for j in 0..=n {
+                if used[j] {
+                    u[row_of[j]] += delta;
+                    v[j] -= delta;
+                } else {
+                    min_v[j] -= delta;
+                }
+            }
+            j0 = j1;
+            if row_of[j0] == 0 {
+                break;
+            }
+        }
+        loop {
+            let j1 = way[j0];
+            row_of[j0] = row_of[j1];
+            j0 = j1;
+            if j0 == 0 {
+                break;
+            }
+        }
+    }
+
+    let mut assignment = vec![None; rows];
+    for (j, &i) in row_of.iter().enumerate().skip(1) {
+        if i > 0 && i <= rows && j <= cols {
+            assignment[i - 1] = Some(j - 1);
+        }
+    }
+    assignment
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn face(x: f32, y: f32) -> Rect {
+        Rect {
+            xy: (x, y).into(),
+            size: (40.0, 40.0).into(),
+        }
+    }
+
+    fn total(cost: &[Vec<f32>], assignment: &[Option<usize>]) -> f32 {
+        assignment
+            .iter()
+            .enumerate()
+            .filter_map(|(i, j)| j.map(|j| cost[i][j]))
+            .sum()
+    }
+
+    #[test]
+    fn hungarian_beats_greedy_with_more_columns() {
+        // greedy takes column 0 for row 0 and leaves row 1 a cost of 9
+        let cost = vec![vec![1.0, 2.0, 9.0], vec![1.0, 9.0, 9.0]];
+        let assignment = hungarian(&cost, 3);
+        assert_eq!(assignment, vec![Some(1), Some(0)]);
+        assert_eq!(total(&cost, &assignment), 3.0);
+    }
+
+    #[test]
+    fn hungarian_beats_greedy_with_more_rows() {
+        let cost = vec![vec![1.0, 2.0], vec![1.0, 9.0], vec![5.0, 5.0]];
+        let assignment = hungarian(&cost, 2);
+        assert_eq!(assignment, vec![Some(1), Some(0), None]);
+        assert_eq!(total(&cost, &assignment), 3.0);
+    }
+
+    #[test]
+    fn hungarian_avoids_infinite_costs() {
+        // taking the cheapest pair first leaves row 0 only its forbidden column
+        let cost = vec![vec![0.5, f32::INFINITY], vec![0.1, 0.2]];
+        assert_eq!(hungarian(&cost, 2), vec![Some(0), Some(1)]);
+        assert_eq!(hungarian(&[], 2), vec![]);
+        assert_eq!(hungarian(&[vec![], vec![]], 0), vec![None, None]);
+    }
+
+    #[test]
+    fn ids_persist_across_frames() {
+        let mut tracker = FaceTracker::default();
+        let ids = tracker.update(&[face(0.0, 0.0), face(200.0, 0.0)]);
+        assert_eq!(ids, vec![0, 1]);
+
+        // both faces move a little and come in the other order
+        for step in 1..=3 {
+            let dx = step as f32 * 5.0;
+            let ids = tracker.update(&[face(200.0 + dx, 0.0), face(dx, 0.0)]);
+            assert_eq!(ids, vec![1, 0]);
+        }
+        assert_eq!(tracker.tracks().len(), 2);
+        assert!(
+            tracker
+                .tracks()
+                .iter()
+                .all(|t| t.state == TrackState::Confirmed && t.hits == 4)
+        );
+
+        // a third face gets a fresh id without disturbing the others
+        let ids = tracker.update(&[face(20.0, 0.0), face(100.0, 200.0), face(220.0, 0.0)]);
+        assert_eq!(ids, vec![0, 2, 1]);
+        assert_eq!(tracker.get(2).unwrap().state, TrackState::Tentative);
+    }
+
+    #[test]
+    fn lost_tracks_resume_until_max_lost() {
+        let mut tracker = FaceTracker {
+            max_lost: 2,
+            ..Default::default()
+        };
+        assert_eq!(tracker.update(&[face(0.0, 0.0)]), vec![0]);
+
+        for misses in 1..=2 {
+            assert!(tracker.update(&[]).is_empty());
+            let track = tracker.get(0).unwrap();
+            assert_eq!(track.state, TrackState::Lost);
+            assert_eq!(track.misses, misses);
+        }
+        assert_eq!(tracker.update(&[face(0.0, 0.0)]), vec![0]);
+
+        for _ in 0..3 {
+            tracker.update(&[]);
+        }
+        assert!(tracker.get(0).is_none());
+        assert_eq!(tracker.update(&[face(0.0, 0.0)]), vec![1]);
+    }
+}
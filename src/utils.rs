@@ -56,13 +56,60 @@ pub fn save_ndarray_as_png_t(array: ArrayView3<f32>, path: &str) -> Result<(), i
     img.save(path)
 }
 
-pub fn scale_rect(r: Rect, scale: (f32, f32)) -> Rect {
-    return Rect {
-        xy: (r.xy.x * scale.0, r.xy.y * scale.1).into(),
-        size: (r.size.width * scale.0, r.size.height * scale.1).into(),
-    };
+/// Intersection over union of two rectangles, 0 when they don't overlap.
+pub fn rect_iou(a: Rect, b: Rect) -> f32 {
+    // computed by hand, nvgx's `Rect::intersect` ignores `self` and `Rect::area` sums the sides
+    let width = f32::min(a.xy.x + a.size.width, b.xy.x + b.size.width) - f32::max(a.xy.x, b.xy.x);
+    let height =
+        f32::min(a.xy.y + a.size.height, b.xy.y + b.size.height) - f32::max(a.xy.y, b.xy.y);
+    if width <= 0.0 || height <= 0.0 {
+        return 0.0;
+    }
+    let i = width * height;
+    let u = a.size.width * a.size.height + b.size.width * b.size.height - i;
+    i / u
 }
 
 pub fn sigmoid(x: &f32) -> f32 {
     1.0 / (1.0 + (-x).exp())
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rect(x: f32, y: f32, width: f32, height: f32) -> Rect {
+        Rect {
+            xy: (x, y).into(),
+            size: (width, height).into(),
+        }
+    }
+
+    #[test]
+    fn rect_iou_of_overlapping_rects() {
+        // intersection 5x5 = 25, union 100 + 100 - 25 = 175
+        let iou = rect_iou(rect(0.0, 0.0, 10.0, 10.0), rect(5.0, 5.0, 10.0, 10.0));
+        assert!((iou - 25.0 / 175.0).abs() < 1e-6);
+
+        // intersection 2x2 = 4, union 8 + 8 - 4 = 12, summing the sides as area gives 0.5
+        let iou = rect_iou(rect(0.0, 0.0, 4.0, 2.0), rect(2.0, 0.0, 4.0, 2.0));
+        assert!((iou - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(
+            iou,
+            rect_iou(rect(2.0, 0.0, 4.0, 2.0), rect(0.0, 0.0, 4.0, 2.0))
+        );
+    }
+
+    #[test]
+    fn rect_iou_of_disjoint_rects() {
+        assert_eq!(
+            rect_iou(rect(0.0, 0.0, 10.0, 10.0), rect(20.0, 0.0, 10.0, 10.0)),
+            0.0
+        );
+        // touching edges don't overlap
+        assert_eq!(
+            rect_iou(rect(0.0, 0.0, 10.0, 10.0), rect(10.0, 0.0, 10.0, 10.0)),
+            0.0
+        );
+    }
+}
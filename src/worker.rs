@@ -0,0 +1,446 @@
+//! Capture and inference on background threads, so the renderer never waits for the camera or
+//! the models.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::bail;
+use tracy_client::span;
+
+use crate::measure_time;
+use crate::pipeline::{DetectionStage, Detections, FrameAnalyzer, FrameResult, LandmarkStage};
+use crate::source::{Frame, FrameSource};
+
+/// A frame copied out of its source, shared by the renderer and the inference thread.
+pub struct CapturedFrame {
+    pub index: u64,
+    pub timestamp: Duration,
+    pub size: (u32, u32),
+    pub data: Vec<u8>,
+    pub captured: Instant,
+}
+
+impl CapturedFrame {
+    fn new(frame: &Frame) -> Self {
+        Self {
+            index: frame.index,
+            timestamp: frame.timestamp,
+            size: frame.size,
+            data: frame.data.to_vec(),
+            captured: Instant::now(),
+        }
+    }
+
+    pub fn frame(&self) -> Frame<'_> {
+        Frame {
+            index: self.index,
+            timestamp: self.timestamp,
+            size: self.size,
+            data: &self.data,
+        }
+    }
+}
+
+/// Opens the frame source on the thread that captures from it.
+pub type OpenSource = Box<dyn FnOnce() -> anyhow::Result<Box<dyn FrameSource>> + Send>;
+
+/// How frames are analyzed.
+pub enum Analysis {
+    /// One thread runs the whole analyzer on a frame before taking the next.
+    Serial(Box<dyn FrameAnalyzer + Send>),
+    /// Detection and landmarks run on their own threads, so detection of a frame overlaps the
+    /// landmarks of the previous one. Throughput is limited by the slower stage instead of
+    /// both together, while each result waits for up to one frame more between the stages.
+    Staged(Box<DetectionStage>, Box<LandmarkStage>),
+}
+
+/// Time spent in each stage of a staged analysis.
+#[derive(Debug, Clone, Copy)]
+pub struct StageTimes {
+    pub detection: Duration,
+    pub landmark: Duration,
+}
+
+/// Result of one analyzed frame.
+pub struct AnalyzedFrame {
+    pub result: FrameResult,
+    /// When the analyzed frame left its source.
+    pub captured: Instant,
+    pub inference_time: Duration,
+    /// `None` unless the analysis is [`Analysis::Staged`].
+    pub stage_times: Option<StageTimes>,
+}
+
+/// A frame on its way from the detection to the landmark stage.
+struct Detected {
+    frame: Arc<CapturedFrame>,
+    detections: Detections,
+    detection_time: Duration,
+}
+
+enum Command {
+    Seek(u64),
+    Resume,
+}
+
+struct Slot<T> {
+    value: Option<T>,
+    closed: bool,
+}
+
+/// Holds the newest value until it is taken, a newer value replaces one nobody took yet.
+struct Latest<T> {
+    slot: Mutex<Slot<T>>,
+    changed: Condvar,
+}
+
+impl<T> Latest<T> {
+    fn new() -> Self {
+        Self {
+            slot: Mutex::new(Slot {
+                value: None,
+                closed: false,
+            }),
+            changed: Condvar::new(),
+        }
+    }
+
+    fn put(&self, value: T) {
+        self.slot.lock().unwrap().value = Some(value);
+        self.changed.notify_all();
+    }
+
+    /// Like [`Latest::put`], but first waits until the previous value was taken.
+    fn put_wait(&self, value: T) {
+        let slot = self.slot.lock().unwrap();
+        let mut slot = self
+            .changed
+            .wait_while(slot, |slot| slot.value.is_some() && !slot.closed)
+            .unwrap();
+        slot.value = Some(value);
+        self.changed.notify_all();
+    }
+
+    fn try_take(&self) -> Option<T> {
+        let value = self.slot.lock().unwrap().value.take();
+        self.changed.notify_all();
+        value
+    }
+
+    /// Waits for a value, `None` once the slot is closed.
+    fn take(&self) -> Option<T> {
+        let slot = self.slot.lock().unwrap();
+        let mut slot = self
+            .changed
+            .wait_while(slot, |slot| slot.value.is_none() && !slot.closed)
+            .unwrap();
+        if slot.closed {
+            return None;
+        }
+        let value = slot.value.take();
+        self.changed.notify_all();
+        value
+    }
+
+    fn close(&self) {
+        self.slot.lock().unwrap().closed = true;
+        self.changed.notify_all();
+    }
+
+    fn is_closed(&self) -> bool {
+        self.slot.lock().unwrap().closed
+    }
+}
+
+struct Shared {
+    /// Newest frame for the renderer.
+    display: Latest<Arc<CapturedFrame>>,
+    /// Newest frame for inference.
+    input: Latest<Arc<CapturedFrame>>,
+    paused: AtomicBool,
+    frame_count: OnceLock<Option<u64>>,
+}
+
+impl Shared {
+    fn close(&self) {
+        self.display.close();
+        self.input.close();
+    }
+}
+
+/// Captures frames on one thread and analyzes them on another. Live sources drop frames the
+/// analyzer has no time for, file sources play at the pace of their timestamps but wait for
+/// the analyzer so every frame is analyzed. Stops when dropped.
+pub struct InferenceWorker {
+    shared: Arc<Shared>,
+    commands: Sender<Command>,
+    results: Receiver<AnalyzedFrame>,
+    threads: Vec<JoinHandle<anyhow::Result<()>>>,
+}
+
+impl InferenceWorker {
+    /// Frames queued between the detection and the landmark stage. One, so the next frame is
+    /// detected while the landmarks of the current one are computed, and a result waits at
+    /// most one frame more. A longer queue would only add latency, the slower stage decides
+    /// the throughput.
+    const STAGE_QUEUE: usize = 1;
+
+    /// Starts the worker threads. The source is opened on the capture thread, as camera
+    /// handles can't always move between threads.
+    pub fn spawn(open_source: OpenSource, analysis: Analysis) -> anyhow::Result<Self> {
+        let shared = Arc::new(Shared {
+            display: Latest::new(),
+            input: Latest::new(),
+            paused: AtomicBool::new(false),
+            frame_count: OnceLock::new(),
+        });
+        let (commands, command_rx) = mpsc::channel();
+        let (result_tx, results) = mpsc::channel();
+
+        let capture = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("capture".into())
+                .spawn(move || {
+                    let result = capture(open_source, &shared, command_rx);
+                    shared.close();
+                    result
+                })?
+        };
+        let mut threads = vec![capture];
+        match analysis {
+            Analysis::Serial(mut analyzer) => {
+                let shared = shared.clone();
+                threads.push(
+                    thread::Builder::new()
+                        .name("inference".into())
+                        .spawn(move || {
+                            let result = infer(analyzer.as_mut(), &shared, result_tx);
+                            shared.close();
+                            result
+                        })?,
+                );
+            }
+            Analysis::Staged(mut detection, mut landmark) => {
+                let (queue, queue_rx) = mpsc::sync_channel(Self::STAGE_QUEUE);
+                let detection_shared = shared.clone();
+                threads.push(
+                    thread::Builder::new()
+                        .name("detection".into())
+                        .spawn(move || {
+                            let result = detect(detection.as_mut(), &detection_shared, queue);
+                            detection_shared.close();
+                            result
+                        })?,
+                );
+                let landmark_shared = shared.clone();
+                threads.push(
+                    thread::Builder::new()
+                        .name("landmark".into())
+                        .spawn(move || {
+                            let result = landmarks(landmark.as_mut(), queue_rx, result_tx);
+                            landmark_shared.close();
+                            result
+                        })?,
+                );
+            }
+        }
+
+        Ok(Self {
+            shared,
+            commands,
+            results,
+            threads,
+        })
+    }
+
+    /// Number of frames of a file source, `None` for live sources or before the source opened.
+    pub fn frame_count(&self) -> Option<u64> {
+        self.shared.frame_count.get().copied().flatten()
+    }
+
+    /// File sources pause by themselves at their end.
+    pub fn paused(&self) -> bool {
+        self.shared.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.shared.paused.store(paused, Ordering::Relaxed);
+        if !paused {
+            let _ = self.commands.send(Command::Resume);
+        }
+    }
+
+    /// Captures the frame with the given index next, also while paused.
+    pub fn seek(&self, index: u64) {
+        let _ = self.commands.send(Command::Seek(index));
+    }
+
+    /// Newest captured frame, `None` if there is none since the last call.
+    pub fn latest_frame(&self) -> Option<Arc<CapturedFrame>> {
+        self.shared.display.try_take()
+    }
+
+    /// Results finished since the last call, oldest first.
+    pub fn results(&self) -> impl Iterator<Item = AnalyzedFrame> + '_ {
+        self.results.try_iter()
+    }
+
+    /// Returns the error that stopped a worker thread.
+    pub fn check(&mut self) -> anyhow::Result<()> {
+        while let Some(idx) = self.threads.iter().position(|t| t.is_finished()) {
+            match self.threads.swap_remove(idx).join() {
+                Ok(result) => result?,
+                Err(_) => bail!("worker thread panicked"),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for InferenceWorker {
+    fn drop(&mut self) {
+        // the capture thread may be blocked on the camera, so the threads aren't joined
+        self.shared.close();
+    }
+}
+
+fn capture(
+    open_source: OpenSource,
+    shared: &Shared,
+    commands: Receiver<Command>,
+) -> anyhow::Result<()> {
+    let mut source = open_source()?;
+    source.start()?;
+    let frame_count = source.frame_count();
+    let _ = shared.frame_count.set(frame_count);
+
+    let mut seek_to = None;
+    // wall clock time and timestamp file playback continues from, reset by seeks and resumes
+    let mut playback: Option<(Instant, Duration)> = None;
+    while !shared.input.is_closed() {
+        for command in commands.try_iter() {
+            playback = None;
+            if let Command::Seek(index) = command {
+                seek_to = Some(index);
+            }
+        }
+        match seek_to.take() {
+            Some(index) => source.seek(index)?,
+            None if shared.paused.load(Ordering::Relaxed) => {
+                // wait for a seek or resume, the worker was dropped if the channel closed
+                playback = None;
+                match commands.recv() {
+                    Ok(Command::Seek(index)) => seek_to = Some(index),
+                    Ok(Command::Resume) => {}
+                    Err(_) => break,
+                }
+                continue;
+            }
+            None => {}
+        }
+
+        let capture = span!("Capture");
+        let mut captured = None;
+        source.with_next_frame(&mut |frame| {
+            captured = Some(CapturedFrame::new(frame));
+            Ok(())
+        })?;
+        drop(capture);
+        let Some(mut frame) = captured else {
+            if frame_count.is_some() {
+                // hold the last frame once a file source runs out
+                shared.paused.store(true, Ordering::Relaxed);
+            }
+            continue;
+        };
+        if frame_count.is_some() {
+            // play file sources at the pace of their timestamps
+            let now = Instant::now();
+            let (start, base) = *playback.get_or_insert((now, frame.timestamp));
+            let due = start + frame.timestamp.saturating_sub(base);
+            match due.checked_duration_since(now) {
+                Some(wait) => thread::sleep(wait),
+                // the analysis fell behind, continue from here instead of rushing to catch up
+                None => playback = Some((now, frame.timestamp)),
+            }
+            // latency counts from when the frame is due
+            frame.captured = Instant::now();
+        }
+        let frame = Arc::new(frame);
+        shared.display.put(frame.clone());
+        match frame_count {
+            Some(_) => shared.input.put_wait(frame),
+            None => shared.input.put(frame),
+        }
+    }
+    Ok(())
+}
+
+fn infer(
+    analyzer: &mut dyn FrameAnalyzer,
+    shared: &Shared,
+    results: Sender<AnalyzedFrame>,
+) -> anyhow::Result<()> {
+    while let Some(frame) = shared.input.take() {
+        let (result, inference_time) = measure_time!({ analyzer.analyze(&frame.frame())? });
+        let analyzed = AnalyzedFrame {
+            result,
+            captured: frame.captured,
+            inference_time,
+            stage_times: None,
+        };
+        if results.send(analyzed).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn detect(
+    stage: &mut DetectionStage,
+    shared: &Shared,
+    queue: SyncSender<Detected>,
+) -> anyhow::Result<()> {
+    while let Some(frame) = shared.input.take() {
+        let (detections, detection_time) = measure_time!({ stage.process(&frame.frame())? });
+        let detected = Detected {
+            frame,
+            detections,
+            detection_time,
+        };
+        // blocks while the landmark stage is behind, the capture thread then drops frames
+        if queue.send(detected).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn landmarks(
+    stage: &mut LandmarkStage,
+    queue: Receiver<Detected>,
+    results: Sender<AnalyzedFrame>,
+) -> anyhow::Result<()> {
+    // ends once the detection thread stops and drops its end of the queue
+    for detected in queue {
+        let (result, landmark_time) =
+            measure_time!({ stage.process(&detected.frame.frame(), detected.detections)? });
+        let analyzed = AnalyzedFrame {
+            result,
+            captured: detected.frame.captured,
+            inference_time: detected.detection_time + landmark_time,
+            stage_times: Some(StageTimes {
+                detection: detected.detection_time,
+                landmark: landmark_time,
+            }),
+        };
+        if results.send(analyzed).is_err() {
+            break;
+        }
+    }
+    Ok(())
+}
@@ -1,126 +1,230 @@
-use fast_image_resize::images::Image;
-use fast_image_resize::{IntoImageView, ResizeOptions, Resizer};
-use ndarray::{Array4, ArrayView};
+use fast_image_resize::images::{CroppedImage, CroppedImageMut, Image};
+use fast_image_resize::{IntoImageView, PixelType, ResizeOptions, Resizer};
 use ndarray::{Axis, s};
-use ort::execution_providers::{CPUExecutionProvider, DirectMLExecutionProvider};
-use ort::inputs;
-use ort::session::Session;
-use rayon::iter::{ParallelBridge, ParallelIterator};
-use result::YoloResult;
+use nvgx::Point;
+use ort::session::{Session, SessionInputValue};
+use ort::value::Tensor;
 use std::path::Path;
+use std::str::FromStr;
 
 use tracy_client::span;
 
+use crate::convert::{Convert, Layout};
+use crate::model::{self, Dim, ModelError, ModelSpec, SessionOptions, Shape, TensorSpec};
+
+mod nms;
 mod result;
+mod tiling;
+
+pub use nms::Nms;
+pub use result::YoloResult;
+pub use tiling::Tiling;
+
+/// How a frame is fitted into the model input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Preprocess {
+    /// Scales the whole frame to fit and pads the rest, every part of the frame is searched.
+    #[default]
+    Letterbox,
+    /// Crops the centred region with the input's aspect ratio, faces near the edges of a wide
+    /// frame are never detected.
+    CenterCrop,
+}
+
+impl FromStr for Preprocess {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "letterbox" | "pad" => Preprocess::Letterbox,
+            "crop" | "center-crop" => Preprocess::CenterCrop,
+            _ => anyhow::bail!("unknown preprocessing `{}`", s),
+        })
+    }
+}
 
 #[allow(unused)]
 pub struct YoloV5Face {
     session: Session,
     resizer: Resizer,
+    /// Resize target, reused across frames.
+    input_image: Image<'static>,
+    /// Model input, allocated on the first run.
+    input: Option<Tensor<f32>>,
+    pub provider: &'static str,
     pub input_shape: (usize, usize),
-    pub output_shape: (usize, usize, usize),
+    /// `None` for dynamic dimensions.
+    pub output_shape: (Option<usize>, Option<usize>, usize),
     pub pos_scale: (f32, f32),
 }
 
 impl YoloV5Face {
-    pub fn new<P: AsRef<Path>>(model: P) -> anyhow::Result<Self> {
-        let session = Session::builder()?
-            .with_execution_providers([
-                DirectMLExecutionProvider::default().build(),
-                CPUExecutionProvider::default().build(),
-            ])?
-            .with_inter_threads(4)?
-            .with_parallel_execution(true)?
-            .with_optimization_level(ort::session::builder::GraphOptimizationLevel::Level3)?
-            .commit_from_file(model)
-            .expect("yolov5 model");
-
-        let dims = &session.inputs[0].input_type.tensor_dimensions().unwrap()[2..];
+    const CONVERT: Convert = Convert::unit(Layout::Nchw);
+
+    pub const SPEC: ModelSpec = ModelSpec {
+        name: "yolov5 face",
+        // [batch, 3, height, width]
+        inputs: &[TensorSpec::f32(Shape::Dims(&[
+            Dim::Any,
+            Dim::Exact(3),
+            Dim::Fixed,
+            Dim::Fixed,
+        ]))
+        .named("input")],
+        // [batch, anchors, 16]
+        outputs: &[
+            TensorSpec::f32(Shape::Dims(&[Dim::Any, Dim::Any, Dim::Exact(16)])).named("output"),
+        ],
+    };
+
+    pub fn new<P: AsRef<Path>>(model: P, options: &SessionOptions) -> Result<Self, ModelError> {
+        let model::Model {
+            session,
+            provider,
+            inputs,
+            outputs,
+        } = model::load(&Self::SPEC, model, options)?;
+
+        let dims = &inputs[0].dims[2..];
         let input_shape = (dims[0] as usize, dims[1] as usize);
-        let dims = session.outputs[0].output_type.tensor_dimensions().unwrap();
-        let output_shape = (dims[0] as usize, dims[1] as usize, dims[2] as usize);
-        let pos_scale = (1.0 / input_shape.0 as f32, 1.0 / input_shape.1 as f32);
+        let dims = &outputs[0].dims;
+        let dim = |v: i64| usize::try_from(v).ok();
+        let output_shape = (dim(dims[0]), dim(dims[1]), dims[2] as usize);
+        // (x, y) from the [height, width] input
+        let pos_scale = (1.0 / input_shape.1 as f32, 1.0 / input_shape.0 as f32);
 
         Ok(Self {
             session,
+            provider,
             input_shape,
             output_shape,
             resizer: Resizer::new(),
+            input_image: Image::new(input_shape.1 as u32, input_shape.0 as u32, PixelType::U8x4),
+            input: None,
             pos_scale,
         })
     }
 
+    /// Detects faces in `src_image`. Boxes and keypoints are in `src_image` pixels.
     pub fn proc_image(
         &mut self,
         src_image: &impl IntoImageView,
+        preprocess: Preprocess,
         conf_th: f32,
         iou_th: f32,
+        nms: Nms,
     ) -> anyhow::Result<Vec<YoloResult>> {
         let _yolov5face = span!("Yolov5 Face");
         _yolov5face.emit_color(0x2f60fe);
 
-        let input_array: Array4<f32> = {
+        let src_size = (src_image.width() as f32, src_image.height() as f32);
+        let dst_size = (self.input_shape.1 as u32, self.input_shape.0 as u32);
+        // source region covered by the whole model input, used to map results back
+        let (input_origin, input_size);
+        {
             let _preproc = span!("Pre Proc");
-            let mut dst_img = Image::new(
-                self.input_shape.0 as u32,
-                self.input_shape.1 as u32,
-                fast_image_resize::PixelType::U8x4,
-            );
-            self.resizer.resize(
-                src_image,
-                &mut dst_img,
-                &ResizeOptions::new().fit_into_destination(None),
-            )?;
-
-            let array_view = ArrayView::from_shape(
-                (1, self.input_shape.0, self.input_shape.1, 4),
+            let dst_img = &mut self.input_image;
+            // padding is YOLOv5's grey
+            dst_img.buffer_mut().fill(114);
+            match preprocess {
+                Preprocess::Letterbox => {
+                    let scale = f32::max(
+                        src_size.0 / dst_size.0 as f32,
+                        src_size.1 / dst_size.1 as f32,
+                    );
+                    let fitted = (
+                        ((src_size.0 / scale).round() as u32).clamp(1, dst_size.0),
+                        ((src_size.1 / scale).round() as u32).clamp(1, dst_size.1),
+                    );
+                    let pad = ((dst_size.0 - fitted.0) / 2, (dst_size.1 - fitted.1) / 2);
+                    let mut fitted_img =
+                        CroppedImageMut::new(dst_img, pad.0, pad.1, fitted.0, fitted.1)?;
+                    self.resizer
+                        .resize(src_image, &mut fitted_img, &ResizeOptions::new())?;
+                    let scale = (src_size.0 / fitted.0 as f32, src_size.1 / fitted.1 as f32);
+                    input_origin = Point::new(-(pad.0 as f32) * scale.0, -(pad.1 as f32) * scale.1);
+                    input_size = (dst_size.0 as f32 * scale.0, dst_size.1 as f32 * scale.1);
+                }
+                Preprocess::CenterCrop => {
+                    self.resizer.resize(
+                        src_image,
+                        dst_img,
+                        &ResizeOptions::new().fit_into_destination(None),
+                    )?;
+                    let scale = f32::min(
+                        src_size.0 / dst_size.0 as f32,
+                        src_size.1 / dst_size.1 as f32,
+                    );
+                    input_size = (dst_size.0 as f32 * scale, dst_size.1 as f32 * scale);
+                    input_origin = Point::new(
+                        (src_size.0 - input_size.0) / 2.0,
+                        (src_size.1 - input_size.1) / 2.0,
+                    );
+                }
+            }
+
+            let (height, width) = self.input_shape;
+            let input = match &mut self.input {
+                Some(input) => input,
+                input => input.insert(model::input_tensor([1, 3, height, width])?),
+            };
+            // [1, h, w, BGRA] -> [1, RGB, h, w]
+            Self::CONVERT.run(
                 dst_img.buffer(),
-            )?
-            .permuted_axes([0, 3, 1, 2]); // [1, w, h, 3] -> [1, 3, w, h]
-
-            let rgb_array_view = array_view.slice(s![.., 0..3;-1, .., ..]);
-
-            let mut input_array: Array4<f32> =
-                unsafe { Array4::uninit(rgb_array_view.dim()).assume_init() };
-            rgb_array_view
-                .axis_iter(Axis(1))
-                .zip(input_array.axis_iter_mut(Axis(1)))
-                .par_bridge()
-                .for_each(|(old, mut new)| {
-                    new.zip_mut_with(&old, |new, old| *new = *old as f32 / 255.0);
-                });
-            input_array
-        };
+                width,
+                height,
+                input.extract_raw_tensor_mut().1,
+            );
+        }
         let outputs = {
             let _inference = span!("Inference");
-            self.session.run(inputs![input_array.view()].unwrap())?
+            let input = self.input.as_ref().unwrap().view();
+            self.session.run([SessionInputValue::from(input)])?
         };
         {
             // [batch_size][4032][16{xyxy:0..4, conf:4, landmarks:5..15, cls:15}]
             let _post_proc = span!("Post Proc");
-            let output = outputs[0].try_extract_tensor::<f32>().unwrap();
+            let output = outputs[0].try_extract_tensor::<f32>()?;
             let output_batch = output.slice(s![0, .., ..]);
 
-            let mut all_results = Vec::new();
-            for elem in output_batch.axis_iter(Axis(0)) {
-                if let Some(e) = YoloResult::new(elem, conf_th, self.pos_scale) {
-                    Self::nms_append(&mut all_results, e, iou_th);
-                }
-            }
-            return Ok(all_results);
+            let candidates: Vec<YoloResult> = output_batch
+                .axis_iter(Axis(0))
+                .filter_map(|elem| YoloResult::new(elem, conf_th, self.pos_scale))
+                .collect();
+            Ok(nms::suppress(candidates, nms, iou_th, conf_th)
+                .iter()
+                .map(|face| face.map_to(input_size, input_origin))
+                .collect())
         }
     }
 
-    #[inline]
-    fn nms_append(results: &mut Vec<YoloResult>, result: YoloResult, iou_th: f32) {
-        for r in results.iter_mut() {
-            if result.iou(r) > iou_th {
-                if result.conf > r.conf {
-                    *r = result;
-                }
-                return;
-            }
+    /// Like [`YoloV5Face::proc_image`], additionally running the detector on overlapping
+    /// `tiling` tiles and suppressing duplicates across the tiles and the whole frame pass.
+    pub fn proc_image_tiled<V: IntoImageView>(
+        &mut self,
+        src_image: &V,
+        tiling: Tiling,
+        preprocess: Preprocess,
+        conf_th: f32,
+        iou_th: f32,
+        nms: Nms,
+    ) -> anyhow::Result<Vec<YoloResult>> {
+        let mut results = self.proc_image(src_image, preprocess, conf_th, iou_th, nms)?;
+        let tiles = tiling.tiles(src_image.width(), src_image.height());
+        if tiles.is_empty() {
+            return Ok(results);
+        }
+
+        let _tiles = span!("Tiles");
+        for (left, top, width, height) in tiles {
+            let tile = CroppedImage::new(src_image, left, top, width, height)?;
+            let offset = Point::new(left as f32, top as f32);
+            results.extend(
+                self.proc_image(&tile, Preprocess::Letterbox, conf_th, iou_th, nms)?
+                    .iter()
+                    .map(|face| face.map_to((1.0, 1.0), offset)),
+            );
         }
-        results.push(result);
+        Ok(nms::suppress(results, nms, iou_th, conf_th))
     }
 }
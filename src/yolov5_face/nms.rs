@@ -0,0 +1,237 @@
+//! Non-maximum suppression of overlapping detections.
+
+use std::str::FromStr;
+
+use anyhow::bail;
+use nvgx::Rect;
+
+use super::YoloResult;
+use crate::utils::rect_iou;
+
+/// How detections overlapping a more confident one are suppressed.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum Nms {
+    /// Drops boxes whose IoU with a kept box exceeds the threshold.
+    #[default]
+    Standard,
+    /// Soft-NMS: scales the confidence of boxes above the IoU threshold by `1 - IoU`.
+    SoftLinear,
+    /// Soft-NMS: scales the confidence of every box by `exp(-IoU² / sigma)`.
+    SoftGaussian { sigma: f32 },
+    /// Like [`Nms::Standard`] with IoU reduced by the normalised distance between the box
+    /// centres, so close but separate faces survive.
+    Diou,
+}
+
+impl Nms {
+    pub const DEFAULT_SIGMA: f32 = 0.5;
+}
+
+impl FromStr for Nms {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.to_ascii_lowercase().as_str() {
+            "standard" | "hard" => Nms::Standard,
+            "soft-linear" | "linear" => Nms::SoftLinear,
+            "soft-gaussian" | "gaussian" => Nms::SoftGaussian {
+                sigma: Nms::DEFAULT_SIGMA,
+            },
+            "diou" => Nms::Diou,
+            _ => bail!("unknown NMS method `{}`", s),
+        })
+    }
+}
+
+/// Suppresses overlapping `results`, always keeping the most confident remaining box first.
+/// Soft variants lower confidences instead of dropping boxes and discard those that fall below
+/// `conf_th`. The kept boxes are sorted by descending confidence.
+pub fn suppress(
+    mut results: Vec<YoloResult>,
+    method: Nms,
+    iou_th: f32,
+    conf_th: f32,
+) -> Vec<YoloResult> {
+    let mut kept = Vec::new();
+    // soft variants change the order, so pick the maximum on every round
+    while let Some(best) = results
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.conf.total_cmp(&b.1.conf))
+        .map(|(idx, _)| idx)
+    {
+        let best = results.swap_remove(best);
+        results.retain_mut(|r| {
+            let weight = match method {
+                Nms::Standard => match best.iou(r) > iou_th {
+                    true => 0.0,
+                    false => 1.0,
+                },
+                Nms::SoftLinear => {
+                    let iou = best.iou(r);
+                    match iou > iou_th {
+                        true => 1.0 - iou,
+                        false => 1.0,
+                    }
+                }
+                Nms::SoftGaussian { sigma } => {
+                    let iou = best.iou(r);
+                    (-iou * iou / sigma).exp()
+                }
+                Nms::Diou => match diou(best.bbox, r.bbox) > iou_th {
+                    true => 0.0,
+                    false => 1.0,
+                },
+            };
+            r.conf *= weight;
+            r.conf > conf_th
+        });
+        kept.push(best);
+    }
+    kept
+}
+
+/// IoU minus the squared distance between the centres over the squared diagonal of the
+/// smallest box enclosing both.
+fn diou(a: Rect, b: Rect) -> f32 {
+    let center = |r: Rect| (r.xy.x + r.size.width / 2.0, r.xy.y + r.size.height / 2.0);
+    let (ca, cb) = (center(a), center(b));
+    let distance = (ca.0 - cb.0).powi(2) + (ca.1 - cb.1).powi(2);
+    let width = f32::max(a.xy.x + a.size.width, b.xy.x + b.size.width) - f32::min(a.xy.x, b.xy.x);
+    let height =
+        f32::max(a.xy.y + a.size.height, b.xy.y + b.size.height) - f32::min(a.xy.y, b.xy.y);
+    let diagonal = width * width + height * height;
+    if diagonal <= 0.0 {
+        return rect_iou(a, b);
+    }
+    rect_iou(a, b) - distance / diagonal
+}
+
+#[cfg(test)]
+mod tests {
+    use nvgx::Point;
+
+    use super::*;
+
+    fn face(conf: f32, x: f32, y: f32, size: f32) -> YoloResult {
+        YoloResult {
+            conf,
+            bbox: Rect {
+                xy: (x, y).into(),
+                size: (size, size).into(),
+            },
+            landmarks: [Point::default(); 5],
+        }
+    }
+
+    fn confs(results: &[YoloResult]) -> Vec<f32> {
+        results.iter().map(|r| r.conf).collect()
+    }
+
+    #[test]
+    fn standard_keeps_most_confident_regardless_of_order() {
+        // merging in output order replaced the first box by the second and the second by the
+        // third, losing the first face which barely overlaps the third
+        let results = vec![
+            face(0.7, 0.0, 0.0, 10.0),
+            face(0.8, 2.0, 0.0, 10.0),
+            face(0.9, 4.0, 0.0, 10.0),
+        ];
+        let kept = suppress(results, Nms::Standard, 0.5, 0.1);
+        assert_eq!(confs(&kept), vec![0.9, 0.7]);
+
+        // merging in output order kept the second box next to the third, which replaced the
+        // first, although the third overlaps both
+        let results = vec![
+            face(0.8, 0.0, 0.0, 10.0),
+            face(0.7, 6.0, 0.0, 10.0),
+            face(0.9, 3.0, 0.0, 10.0),
+        ];
+        let kept = suppress(results, Nms::Standard, 0.3, 0.1);
+        assert_eq!(confs(&kept), vec![0.9]);
+    }
+
+    #[test]
+    fn standard_keeps_separate_faces_sorted() {
+        let results = vec![
+            face(0.7, 0.0, 0.0, 10.0),
+            face(0.9, 50.0, 0.0, 10.0),
+            face(0.8, 1.0, 1.0, 10.0),
+            face(0.75, 51.0, 0.0, 10.0),
+        ];
+        let kept = suppress(results, Nms::Standard, 0.5, 0.1);
+        assert_eq!(confs(&kept), vec![0.9, 0.8]);
+        assert_eq!(kept[0].bbox.xy.x, 50.0);
+        assert_eq!(kept[1].bbox.xy.x, 1.0);
+    }
+
+    #[test]
+    fn standard_keeps_boxes_at_threshold() {
+        // IoU of exactly 1/3
+        let kept = suppress(
+            vec![face(0.9, 0.0, 0.0, 10.0), face(0.8, 5.0, 0.0, 10.0)],
+            Nms::Standard,
+            1.0 / 3.0,
+            0.1,
+        );
+        assert_eq!(kept.len(), 2);
+    }
+
+    #[test]
+    fn soft_linear_decays_overlapping() {
+        // IoU 0.6
+        let results = vec![face(0.9, 0.0, 0.0, 10.0), face(0.8, 2.5, 0.0, 10.0)];
+        let kept = suppress(results.clone(), Nms::SoftLinear, 0.5, 0.1);
+        assert_eq!(kept.len(), 2);
+        assert!((kept[1].conf - 0.8 * 0.4).abs() < 1e-5);
+
+        // decayed below the confidence threshold
+        let kept = suppress(results.clone(), Nms::SoftLinear, 0.5, 0.5);
+        assert_eq!(confs(&kept), vec![0.9]);
+
+        // below the IoU threshold nothing changes
+        let kept = suppress(results, Nms::SoftLinear, 0.7, 0.1);
+        assert_eq!(confs(&kept), vec![0.9, 0.8]);
+    }
+
+    #[test]
+    fn soft_gaussian_decays_by_overlap() {
+        let results = vec![
+            face(0.9, 0.0, 0.0, 10.0),
+            face(0.8, 2.5, 0.0, 10.0),
+            face(0.7, 100.0, 0.0, 10.0),
+        ];
+        let kept = suppress(results, Nms::SoftGaussian { sigma: 0.5 }, 0.5, 0.1);
+        assert_eq!(kept.len(), 3);
+        assert_eq!(kept[0].conf, 0.9);
+        // the distant face is untouched and now ranks before the decayed one
+        assert_eq!(kept[1].conf, 0.7);
+        let expected = 0.8 * f32::exp(-0.36 / 0.5);
+        assert!((kept[2].conf - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn diou_keeps_adjacent_faces() {
+        // concentric boxes keep their IoU, a box shifted away is penalised by its distance
+        let inner = vec![face(0.9, 0.0, 0.0, 20.0), face(0.8, 5.0, 5.0, 10.0)];
+        let outer = vec![face(0.9, 0.0, 0.0, 10.0), face(0.8, 4.0, 0.0, 10.0)];
+        assert_eq!(suppress(inner.clone(), Nms::Standard, 0.2, 0.1).len(), 1);
+        assert_eq!(suppress(inner, Nms::Diou, 0.2, 0.1).len(), 1);
+        assert_eq!(suppress(outer.clone(), Nms::Standard, 0.4, 0.1).len(), 1);
+        assert_eq!(suppress(outer, Nms::Diou, 0.4, 0.1).len(), 2);
+    }
+
+    #[test]
+    fn diou_of_identical_boxes_is_one() {
+        let r = face(1.0, 3.0, 4.0, 10.0).bbox;
+        assert!((diou(r, r) - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parses_methods() {
+        assert_eq!("hard".parse::<Nms>().unwrap(), Nms::Standard);
+        assert_eq!("Soft-Linear".parse::<Nms>().unwrap(), Nms::SoftLinear);
+        assert_eq!("diou".parse::<Nms>().unwrap(), Nms::Diou);
+        assert!("fast".parse::<Nms>().is_err());
+    }
+}
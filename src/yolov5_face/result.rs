@@ -3,7 +3,10 @@ use std::fmt::Debug;
 use ndarray::{ArrayBase, Ix1};
 use nvgx::{Point, Rect, Vector2D};
 
+use crate::utils::rect_iou;
+
 #[allow(unused)]
+#[derive(Clone)]
 pub struct YoloResult {
     pub conf: f32,
     pub bbox: Rect,
@@ -58,12 +61,25 @@ impl YoloResult {
         };
     }
 
-    pub fn iou(&self, rhs: &Self) -> f32 {
-        let i = self.bbox.intersect(rhs.bbox).area();
-        if i < f32::EPSILON {
-            return 0.0;
+    /// Maps a result from normalised model coordinates into an image region of `size` pixels
+    /// starting at `offset`.
+    pub fn map_to(&self, size: (f32, f32), offset: Point) -> Self {
+        let map = |p: Point| Point::new(offset.x + p.x * size.0, offset.y + p.y * size.1);
+        Self {
+            conf: self.conf,
+            bbox: Rect {
+                xy: map(self.bbox.xy),
+                size: (
+                    self.bbox.size.width * size.0,
+                    self.bbox.size.height * size.1,
+                )
+                    .into(),
+            },
+            landmarks: self.landmarks.map(map),
         }
-        let u = (self.bbox.area() + rhs.bbox.area()) - i;
-        return i / u;
+    }
+
+    pub fn iou(&self, rhs: &Self) -> f32 {
+        rect_iou(self.bbox, rhs.bbox)
     }
 }
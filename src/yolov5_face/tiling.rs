@@ -0,0 +1,112 @@
+//! Splitting large frames into overlapping detector tiles.
+
+/// Overlapping square tiles of `size` source pixels, run in addition to a pass over the whole
+/// frame so small faces keep enough pixels after scaling to the model input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tiling {
+    pub size: u32,
+    /// Pixels shared by neighbouring tiles, should be at least the size of the smallest face
+    /// of interest so every face lies entirely inside some tile.
+    pub overlap: u32,
+}
+
+impl Tiling {
+    /// Tiles covering a `width` x `height` frame as `(left, top, width, height)`, none when a
+    /// single tile would cover it anyway.
+    pub fn tiles(&self, width: u32, height: u32) -> Vec<(u32, u32, u32, u32)> {
+        if width <= self.size && height <= self.size {
+            return Vec::new();
+        }
+        let xs = self.starts(width);
+        let ys = self.starts(height);
+        ys.iter()
+            .flat_map(|&y| {
+                xs.iter()
+                    .map(move |&x| (x, y, self.size.min(width), self.size.min(height)))
+            })
+            .collect()
+    }
+
+    /// Evenly spread tile starts along an axis of `length` pixels.
+    fn starts(&self, length: u32) -> Vec<u32> {
+        if length <= self.size {
+            return vec![0];
+        }
+        let stride = self.size.saturating_sub(self.overlap).max(1);
+        let count = length.saturating_sub(self.overlap).div_ceil(stride).max(2);
+        let last = length - self.size;
+        (0..count)
+            .map(|i| (last as u64 * i as u64 / (count - 1) as u64) as u32)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TILING: Tiling = Tiling {
+        size: 1280,
+        overlap: 160,
+    };
+
+    /// Checks the tiles cover the frame with at least `overlap` pixels between neighbours and
+    /// returns the distinct starts along x and y.
+    fn check(tiling: Tiling, width: u32, height: u32) -> (Vec<u32>, Vec<u32>) {
+        let tiles = tiling.tiles(width, height);
+        let mut xs: Vec<u32> = tiles.iter().map(|t| t.0).collect();
+        let mut ys: Vec<u32> = tiles.iter().map(|t| t.1).collect();
+        xs.sort();
+        xs.dedup();
+        ys.sort();
+        ys.dedup();
+        assert_eq!(tiles.len(), xs.len() * ys.len());
+        for &(x, y, w, h) in &tiles {
+            assert_eq!((w, h), (tiling.size.min(width), tiling.size.min(height)));
+            assert!(x + w <= width && y + h <= height);
+        }
+        for (starts, length) in [(&xs, width), (&ys, height)] {
+            let size = tiling.size.min(length);
+            assert_eq!(starts[0], 0);
+            assert_eq!(starts[starts.len() - 1] + size, length);
+            for pair in starts.windows(2) {
+                assert!(pair[0] < pair[1]);
+                assert!(pair[0] + size >= pair[1] + tiling.overlap);
+            }
+        }
+        (xs, ys)
+    }
+
+    #[test]
+    fn covers_frame_with_overlap() {
+        for (width, height) in [(1920, 1080), (2000, 1300), (4096, 2160), (7680, 4320)] {
+            check(TILING, width, height);
+        }
+    }
+
+    #[test]
+    fn no_tiles_for_frames_within_one_tile() {
+        assert!(TILING.tiles(1280, 1280).is_empty());
+        assert!(TILING.tiles(640, 480).is_empty());
+        // only the longer axis is split
+        let (xs, ys) = check(TILING, 3840, 720);
+        assert_eq!(xs.len(), 4);
+        assert_eq!(ys, vec![0]);
+    }
+
+    #[test]
+    fn exact_multiple_of_stride() {
+        // 2 * 1120 + 160 and 3 * 1120 + 160, tiles step by exactly the stride
+        let (xs, ys) = check(TILING, 3520, 2400);
+        assert_eq!(xs, vec![0, 1120, 2240]);
+        assert_eq!(ys, vec![0, 1120]);
+    }
+
+    #[test]
+    fn spreads_remainder_evenly() {
+        // 3680 / 1120 leaves a remainder, four tiles overlap by more than required
+        let (xs, ys) = check(TILING, 3840, 2160);
+        assert_eq!(xs, vec![0, 853, 1706, 2560]);
+        assert_eq!(ys, vec![0, 880]);
+    }
+}